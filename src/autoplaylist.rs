@@ -0,0 +1,216 @@
+use crate::query::{self, Evaluator};
+use crate::sort::{self, Direction, SortKey};
+use crate::track::Track;
+use crate::types::Error;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A titleformat pattern an autoplaylist is sorted by
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SortPattern {
+    pub pattern: String,
+    pub direction: Direction,
+}
+
+/// Which of the matching tracks end up in an autoplaylist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Selection {
+    /// All the matching tracks
+    #[default]
+    All,
+    /// The first `n` matching tracks once sorted, e.g. the top 50
+    First(usize),
+    /// `count` matching tracks picked at random, the same ones for the same
+    /// `seed` and library
+    Random { count: usize, seed: u64 },
+}
+
+/// A smart playlist: the tracks matching a query, sorted by titleformat
+/// patterns and optionally limited.  The definition only holds text so it
+/// can be stored and shared, with the `serde` feature it can be
+/// (de)serialized.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::autoplaylist::{Autoplaylist, Selection};
+/// # use titleformat_rs::sort::Direction;
+/// # use std::collections::HashMap;
+/// let tracks: Vec<HashMap<String, Vec<String>>> = ["3", "5", "1", "4"]
+///     .iter()
+///     .map(|r| HashMap::from([("rating".to_string(), vec![r.to_string()])]))
+///     .collect();
+/// let mut top = Autoplaylist::new("Top rated", "%rating% GREATER 2");
+/// top.add_sort("%rating%", Direction::Descending);
+/// top.selection = Selection::First(2);
+/// assert_eq!(top.evaluate(&tracks).unwrap(), vec![1, 3]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Autoplaylist {
+    pub name: String,
+    /// The foobar2000 query selecting the tracks
+    pub query: String,
+    /// The sort patterns, later ones break the ties of earlier ones
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort: Vec<SortPattern>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub selection: Selection,
+}
+
+/* xorshift64*, good enough to pick tracks and reproducible everywhere */
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        /* the state must never be zero */
+        Rng(if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        })
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n as u64) as usize
+    }
+}
+
+impl Autoplaylist {
+    /// Constructs a new unsorted `Autoplaylist` with all the tracks matching
+    /// `query`
+    pub fn new(name: &str, query: &str) -> Self {
+        Autoplaylist {
+            name: String::from(name),
+            query: String::from(query),
+            sort: vec![],
+            selection: Selection::All,
+        }
+    }
+
+    /// Adds a titleformat pattern to sort by
+    pub fn add_sort(&mut self, pattern: &str, direction: Direction) {
+        self.sort.push(SortPattern {
+            pattern: String::from(pattern),
+            direction,
+        });
+    }
+
+    /// Returns the indices of the tracks of the playlist, in order
+    pub fn evaluate<T: Track>(&self, tracks: &[T]) -> Result<Vec<usize>, Error> {
+        self.evaluate_with(&Evaluator::new(), tracks)
+    }
+
+    /// Returns the indices of the tracks of the playlist, in order, using
+    /// `evaluator` for the query, e.g. to fix the time for
+    /// `DURING LAST 2 WEEKS`
+    pub fn evaluate_with<T: Track>(
+        &self,
+        evaluator: &Evaluator,
+        tracks: &[T],
+    ) -> Result<Vec<usize>, Error> {
+        let query = query::parse(&self.query)?;
+        let keys = self
+            .sort
+            .iter()
+            .map(|s| SortKey::parse(&s.pattern, s.direction))
+            .collect::<Result<Vec<SortKey>, Error>>()?;
+        let mut indices = evaluator.filter(&query, tracks)?;
+
+        match self.selection {
+            Selection::All => sort::sort_indices(tracks, &mut indices, &keys)?,
+            Selection::First(n) => {
+                sort::sort_indices(tracks, &mut indices, &keys)?;
+                indices.truncate(n);
+            }
+            Selection::Random { count, seed } => {
+                /* pick first and only sort what was picked */
+                let mut rng = Rng::new(seed);
+                let count = count.min(indices.len());
+                for i in 0..count {
+                    let j = i + rng.below(indices.len() - i);
+                    indices.swap(i, j);
+                }
+                indices.truncate(count);
+                sort::sort_indices(tracks, &mut indices, &keys)?;
+            }
+        }
+        Ok(indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn tracks(n: usize) -> Vec<HashMap<String, Vec<String>>> {
+        (0..n)
+            .map(|i| HashMap::from([(String::from("n"), vec![i.to_string()])]))
+            .collect()
+    }
+
+    #[test]
+    fn test_all_sorted() {
+        let mut playlist = Autoplaylist::new("Middle", "n GREATER 2 AND n LESS 5");
+        assert_eq!(playlist.evaluate(&tracks(6)).unwrap(), vec![3, 4]);
+        playlist.add_sort("%n%", Direction::Descending);
+        assert_eq!(playlist.evaluate(&tracks(6)).unwrap(), vec![4, 3]);
+    }
+
+    #[test]
+    fn test_random() {
+        let mut playlist = Autoplaylist::new("Random", "ALL");
+        playlist.selection = Selection::Random { count: 5, seed: 42 };
+        let library = tracks(100);
+        let picked = playlist.evaluate(&library).unwrap();
+        assert_eq!(picked.len(), 5);
+        assert_eq!(picked, playlist.evaluate(&library).unwrap());
+        let mut unique = picked.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 5);
+
+        playlist.add_sort("%n%", Direction::Ascending);
+        let sorted = playlist.evaluate(&library).unwrap();
+        assert_eq!(sorted, unique);
+
+        playlist.selection = Selection::Random { count: 5, seed: 0 };
+        assert_eq!(playlist.evaluate(&tracks(3)).unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let playlist = Autoplaylist::new("Broken", "n HAS");
+        assert!(playlist.evaluate(&tracks(1)).is_err());
+        let mut playlist = Autoplaylist::new("Broken", "ALL");
+        playlist.add_sort("$add()", Direction::Ascending);
+        assert!(playlist.evaluate(&tracks(1)).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut playlist = Autoplaylist::new("Top", "%rating% GREATER 3");
+        playlist.add_sort("%rating%", Direction::Descending);
+        playlist.selection = Selection::First(50);
+        let json = serde_json::to_string(&playlist).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"Top","query":"%rating% GREATER 3","sort":[{"pattern":"%rating%","direction":"Descending"}],"selection":{"First":50}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Autoplaylist>(&json).unwrap(),
+            playlist
+        );
+        let minimal: Autoplaylist =
+            serde_json::from_str(r#"{"name":"All","query":"ALL"}"#).unwrap();
+        assert_eq!(minimal, Autoplaylist::new("All", "ALL"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::environment::{Environment, Value};
+use crate::functions::{edit, mp3tag, picard};
+use crate::parser;
+use crate::program::Program;
+use crate::types::{Error, Expr};
+
+/* functions of a dialect get their arguments unevaluated so they can
+ * evaluate them lazily, repeatedly or look at how they are written */
+pub(crate) type LazyFn = fn(&Program, &mut Environment, &[Vec<Expr>]) -> Result<Value, Error>;
+
+/// The flavour of the language a [`Program`] is parsed and evaluated as.
+/// The dialects differ in their functions and in what counts as true,
+/// Picard also in how scripts are written.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::dialect::Dialect;
+/// # use titleformat_rs::program::Program;
+/// # use std::collections::HashMap;
+/// let mut program = Program::new();
+/// program.set_dialect(Dialect::Picard);
+/// program.parse("$set(_n,$lenmulti(%artist%))$if(%_n%,%_n% artists)").unwrap();
+/// let mut metadata = HashMap::new();
+/// metadata.insert("artist".into(), vec!["A".into(), "B".into()]);
+/// assert_eq!(program.run_with_meta(metadata).unwrap(), "2 artists");
+///
+/// program.set_dialect(Dialect::Mp3tag);
+/// program.parse("$num(%track%,3)[ - %title%]$if($grtr(%track%,9),!)").unwrap();
+/// let mut metadata = HashMap::new();
+/// metadata.insert("TRACK".into(), vec!["12/14".into()]);
+/// assert_eq!(program.run_with_meta(metadata).unwrap(), "012!");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// foobar2000 titleformatting: fields and functions are true when they
+    /// are set, literals are always true.  `$set()`, `$append()` and
+    /// `$unset()` change the tags and only exist when the program is run
+    /// with [`Program::run_edit`], elsewhere they are unknown functions.
+    #[default]
+    Foobar2000,
+    /// MusicBrainz Picard tagger scripts: a value is true when it is not
+    /// empty, missing fields are empty, multi-value tags are joined with
+    /// `"; "` and `$set()` and friends change the tags as the script runs.
+    /// Variables whose name starts with `_` are hidden, they are never
+    /// written as tags.  `$append()` is added and the functions of
+    /// foobar2000 not overridden by Picard remain available.
+    /// Scripts are written as in Picard: `'` and `[` are plain text, `\`
+    /// escapes `$`, `%`, `(`, `)`, `,` and `\` or makes `\n` and `\t`, and
+    /// lines starting with `#` are comments.
+    Picard,
+    /// Mp3tag format strings: `[...]` is shown when a field or function in
+    /// it is not empty, literals do not count.  Missing fields are empty,
+    /// multi-value fields are joined with `\\` and their names are case
+    /// insensitive.  The conditions of `$if()` and friends are true when
+    /// not empty and not `0`, the comparisons return `1` or `0`.  Mp3tag's
+    /// `$regexp()`, `$validate()`, `$num()` and `$replace()` are used, the
+    /// functions of foobar2000 with no Mp3tag counterpart remain available.
+    /// Scripts are parsed as in foobar2000, so a `$` in literal text such as the `$1` of a
+    /// `$regexp()` replacement has to be quoted: `'$1'`.
+    Mp3tag,
+}
+
+impl Dialect {
+    /* Picard scripts have their own syntax, the others are written as in
+     * foobar2000 */
+    pub(crate) fn parse(self, input: &str) -> Result<Vec<Expr>, Error> {
+        match self {
+            Dialect::Foobar2000 | Dialect::Mp3tag => parser::parse(input),
+            Dialect::Picard => picard::parse(input),
+        }
+    }
+
+    /* the functions of the dialect, consulted after the functions inserted
+     * into the registry but before its built-in ones.  foobar2000 only
+     * changes tags when asked to, outside run_edit $set() is unknown */
+    pub(crate) fn function(self, name: &str, editing: bool) -> Option<LazyFn> {
+        let table: &[(&str, LazyFn)] = match self {
+            Dialect::Foobar2000 if editing => &edit::FUNCTIONS,
+            Dialect::Foobar2000 => &[],
+            Dialect::Picard => &picard::FUNCTIONS,
+            Dialect::Mp3tag => &mp3tag::FUNCTIONS,
+        };
+        table
+            .binary_search_by(|(f, _)| (*f).cmp(name))
+            .ok()
+            .map(|i| table[i].1)
+    }
+
+    /* looks up %name% */
+    pub(crate) fn variable(self, env: &Environment, name: &str) -> Value {
+        match self {
+            Dialect::Foobar2000 => env.get_variable(name),
+            Dialect::Picard => picard::variable(env, name),
+            Dialect::Mp3tag => mp3tag::variable(env, name),
+        }
+    }
+
+    /* variables that are not tags */
+    pub(crate) fn is_hidden(self, name: &str) -> bool {
+        match self {
+            Dialect::Foobar2000 | Dialect::Mp3tag => false,
+            Dialect::Picard => name.starts_with('_'),
+        }
+    }
+
+    /* applies the truth semantics of the dialect to the value of expr */
+    pub(crate) fn truth(self, expr: &Expr, value: Value) -> Value {
+        match (self, expr) {
+            /* already resolved */
+            (Dialect::Foobar2000, _) | (_, Expr::ExprValue(_)) => value,
+            (Dialect::Mp3tag, Expr::Literal(_)) => Value {
+                cond: false,
+                val: value.val,
+            },
+            (Dialect::Picard, _) | (Dialect::Mp3tag, _) => Value {
+                cond: !value.val.is_empty(),
+                val: value.val,
+            },
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+/// How a tag was changed by a script
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    /// The tag has different values, or the same values in another order
+    Changed(String),
+    Removed(String),
+}
+
+/// The tags of a track after running a script with
+/// [`Program::run_edit`](crate::program::Program::run_edit), with what
+/// changed so it can be reviewed before the tags are written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub metadata: HashMap<String, Vec<String>>,
+    /// Sorted by tag name
+    pub changes: Vec<Change>,
+}
+
+impl Edit {
+    /// Returns whether the script changed any tag
+    pub fn is_changed(&self) -> bool {
+        !self.changes.is_empty()
+    }
+}
+
+/// Returns how the tags changed from `before` to `after`, sorted by tag
+/// name.  A tag without values counts as missing.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::edit::{changes, Change};
+/// # use std::collections::HashMap;
+/// let before = HashMap::from([
+///     ("album".to_string(), vec!["A".to_string()]),
+///     ("date".to_string(), vec!["1999".to_string()]),
+/// ]);
+/// let after = HashMap::from([
+///     ("album".to_string(), vec!["B".to_string()]),
+///     ("genre".to_string(), vec!["Rock".to_string()]),
+/// ]);
+/// assert_eq!(
+///     changes(&before, &after),
+///     vec![
+///         Change::Changed("album".to_string()),
+///         Change::Removed("date".to_string()),
+///         Change::Added("genre".to_string()),
+///     ]
+/// );
+/// ```
+pub fn changes(
+    before: &HashMap<String, Vec<String>>,
+    after: &HashMap<String, Vec<String>>,
+) -> Vec<Change> {
+    let values = |map: &HashMap<String, Vec<String>>, key: &str| -> Option<Vec<String>> {
+        map.get(key).filter(|v| !v.is_empty()).cloned()
+    };
+    let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| match (values(before, key), values(after, key)) {
+            (None, Some(_)) => Some(Change::Added(key.clone())),
+            (Some(_), None) => Some(Change::Removed(key.clone())),
+            (Some(a), Some(b)) if a != b => Some(Change::Changed(key.clone())),
+            _ => None,
+        })
+        .collect()
+}
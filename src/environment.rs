@@ -1,4 +1,6 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 
 use crate::types::Error;
 use crate::types::Error::*;
@@ -6,6 +8,11 @@ use std::cell::RefCell;
 
 use crate::functions;
 use crate::functions::num::to_int;
+use crate::path::PathMode;
+use crate::playback::{self, PlaybackState};
+use crate::playlist::{self, ListPosition, Playlist};
+use crate::remap::Remapping;
+use crate::track::Track;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Value {
@@ -28,203 +35,313 @@ pub enum FuncValue {
     NativeEnvFnError(fn(&Environment, Vec<Value>) -> Result<Value, Error>),
 }
 
-/* everything is a string... */
-/* like a register file in a cpu, but with strings! */
-#[derive(Clone)]
-pub struct Environment {
-    vars: RefCell<HashMap<String, String>>,
-    metadata: HashMap<String, Vec<String>>,
-    funcs: HashMap<String, FuncValue>,
+/* written out as older compilers cannot derive Debug for the higher-ranked
+ * function pointer of NativeEnvFnError */
+impl fmt::Debug for FuncValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, ptr) = match self {
+            FuncValue::NativeFn(func) => ("NativeFn", *func as usize),
+            FuncValue::NativeFnError(func) => ("NativeFnError", *func as usize),
+            FuncValue::NativeCondFnError(func) => ("NativeCondFnError", *func as usize),
+            FuncValue::NativeEnvFnError(func) => ("NativeEnvFnError", *func as usize),
+        };
+        f.debug_tuple(name).field(&(ptr as *const ())).finish()
+    }
 }
 
-impl Environment {
-    fn add_default_functions(&mut self) {
-        self.funcs.insert(
-            String::from("add"),
-            FuncValue::NativeFnError(functions::num::add::add),
-        );
-        self.funcs.insert(
-            String::from("sub"),
-            FuncValue::NativeFnError(functions::num::sub::sub),
-        );
-        self.funcs.insert(
-            String::from("mul"),
-            FuncValue::NativeFnError(functions::num::mul::mul),
-        );
-        self.funcs.insert(
-            String::from("div"),
-            FuncValue::NativeFnError(functions::num::div::div),
-        );
-        self.funcs.insert(
-            String::from("min"),
-            FuncValue::NativeFnError(functions::num::min::min),
-        );
-        self.funcs.insert(
-            String::from("max"),
-            FuncValue::NativeFnError(functions::num::max::max),
-        );
+/* the built-in functions, sorted by name so lookups can use a binary search */
+static BUILTINS: [(&str, FuncValue); 53] = [
+    ("add", FuncValue::NativeFnError(functions::num::add::add)),
+    (
+        "and",
+        FuncValue::NativeCondFnError(functions::control::and::and),
+    ),
+    (
+        "channels",
+        FuncValue::NativeEnvFnError(functions::info::channels),
+    ),
+    ("codec", FuncValue::NativeEnvFnError(functions::info::codec)),
+    (
+        "crlf",
+        FuncValue::NativeCondFnError(functions::str::constants::crlf),
+    ),
+    (
+        "cut",
+        FuncValue::NativeCondFnError(functions::str::modify::cut),
+    ),
+    ("div", FuncValue::NativeFnError(functions::num::div::div)),
+    (
+        "eq",
+        FuncValue::NativeCondFnError(functions::num::control::eq),
+    ),
+    (
+        "firstalphachar",
+        FuncValue::NativeCondFnError(functions::str::case::firstalphachar),
+    ),
+    (
+        "get",
+        FuncValue::NativeEnvFnError(|env, args| env.get_value(args)),
+    ),
+    (
+        "greater",
+        FuncValue::NativeCondFnError(functions::num::control::greater),
+    ),
+    (
+        "group_common",
+        FuncValue::NativeEnvFnError(functions::aggregate::group_common),
+    ),
+    (
+        "group_distinct",
+        FuncValue::NativeEnvFnError(functions::aggregate::group_distinct),
+    ),
+    (
+        "group_max",
+        FuncValue::NativeEnvFnError(functions::aggregate::group_max),
+    ),
+    (
+        "group_min",
+        FuncValue::NativeEnvFnError(functions::aggregate::group_min),
+    ),
+    (
+        "group_sum",
+        FuncValue::NativeEnvFnError(functions::aggregate::group_sum),
+    ),
+    (
+        "gt",
+        FuncValue::NativeCondFnError(functions::num::control::gt),
+    ),
+    (
+        "gte",
+        FuncValue::NativeCondFnError(functions::num::control::gte),
+    ),
+    (
+        "if",
+        FuncValue::NativeCondFnError(functions::control::if_::if_),
+    ),
+    (
+        "if2",
+        FuncValue::NativeCondFnError(functions::control::if2::if2),
+    ),
+    (
+        "if3",
+        FuncValue::NativeCondFnError(functions::control::if3::if3),
+    ),
+    (
+        "ifequal",
+        FuncValue::NativeCondFnError(functions::control::ifequal::ifequal),
+    ),
+    (
+        "ifgreater",
+        FuncValue::NativeCondFnError(functions::control::ifgreater::ifgreater),
+    ),
+    (
+        "iflonger",
+        FuncValue::NativeCondFnError(functions::control::iflonger::iflonger),
+    ),
+    ("info", FuncValue::NativeEnvFnError(functions::info::info)),
+    (
+        "left",
+        FuncValue::NativeCondFnError(functions::str::modify::left),
+    ),
+    (
+        "len",
+        FuncValue::NativeCondFnError(functions::str::size::len),
+    ),
+    (
+        "longer",
+        FuncValue::NativeCondFnError(functions::str::size::longer),
+    ),
+    (
+        "lower",
+        FuncValue::NativeCondFnError(functions::str::case::lower),
+    ),
+    (
+        "lt",
+        FuncValue::NativeCondFnError(functions::num::control::lt),
+    ),
+    (
+        "lte",
+        FuncValue::NativeCondFnError(functions::num::control::lte),
+    ),
+    ("max", FuncValue::NativeFnError(functions::num::max::max)),
+    (
+        "meta",
+        FuncValue::NativeEnvFnError(|env, args| env.meta_value(args)),
+    ),
+    (
+        "meta_num",
+        FuncValue::NativeEnvFnError(|env, args| env.meta_num_value(args)),
+    ),
+    (
+        "meta_sep",
+        FuncValue::NativeEnvFnError(|env, args| env.meta_sep_value(args)),
+    ),
+    (
+        "meta_test",
+        FuncValue::NativeEnvFnError(|env, args| env.meta_test_value(args)),
+    ),
+    ("min", FuncValue::NativeFnError(functions::num::min::min)),
+    ("mul", FuncValue::NativeFnError(functions::num::mul::mul)),
+    (
+        "ne",
+        FuncValue::NativeCondFnError(functions::num::control::ne),
+    ),
+    (
+        "noop",
+        FuncValue::NativeCondFnError(functions::str::constants::noop),
+    ),
+    (
+        "not",
+        FuncValue::NativeCondFnError(functions::control::not::not),
+    ),
+    (
+        "num",
+        FuncValue::NativeCondFnError(functions::str::format::num),
+    ),
+    (
+        "or",
+        FuncValue::NativeCondFnError(functions::control::or::or),
+    ),
+    (
+        "put",
+        FuncValue::NativeEnvFnError(|env, args| env.put_value(args)),
+    ),
+    (
+        "puts",
+        FuncValue::NativeEnvFnError(|env, args| env.puts_value(args)),
+    ),
+    (
+        "select",
+        FuncValue::NativeCondFnError(functions::control::select::select),
+    ),
+    (
+        "stripprefix",
+        FuncValue::NativeCondFnError(functions::str::modify::stripprefix),
+    ),
+    ("sub", FuncValue::NativeFnError(functions::num::sub::sub)),
+    (
+        "swapprefix",
+        FuncValue::NativeCondFnError(functions::str::modify::swapprefix),
+    ),
+    (
+        "tab",
+        FuncValue::NativeCondFnError(functions::str::constants::tab),
+    ),
+    (
+        "upper",
+        FuncValue::NativeCondFnError(functions::str::case::upper),
+    ),
+    (
+        "xor",
+        FuncValue::NativeCondFnError(functions::control::xor::xor),
+    ),
+    (
+        "year",
+        FuncValue::NativeCondFnError(functions::str::datetime::year),
+    ),
+];
 
-        self.funcs.insert(
-            String::from("eq"),
-            FuncValue::NativeCondFnError(functions::num::control::eq),
-        );
-        self.funcs.insert(
-            String::from("ne"),
-            FuncValue::NativeCondFnError(functions::num::control::ne),
-        );
-        self.funcs.insert(
-            String::from("gt"),
-            FuncValue::NativeCondFnError(functions::num::control::gt),
-        );
-        self.funcs.insert(
-            String::from("gte"),
-            FuncValue::NativeCondFnError(functions::num::control::gte),
-        );
-        self.funcs.insert(
-            String::from("lt"),
-            FuncValue::NativeCondFnError(functions::num::control::lt),
-        );
-        self.funcs.insert(
-            String::from("lte"),
-            FuncValue::NativeCondFnError(functions::num::control::lte),
-        );
-
-        self.funcs.insert(
-            String::from("if"),
-            FuncValue::NativeCondFnError(functions::control::if_::if_),
-        );
-        self.funcs.insert(
-            String::from("if2"),
-            FuncValue::NativeCondFnError(functions::control::if2::if2),
-        );
-        self.funcs.insert(
-            String::from("if3"),
-            FuncValue::NativeCondFnError(functions::control::if3::if3),
-        );
-        self.funcs.insert(
-            String::from("ifequal"),
-            FuncValue::NativeCondFnError(functions::control::ifequal::ifequal),
-        );
-        self.funcs.insert(
-            String::from("ifgreater"),
-            FuncValue::NativeCondFnError(functions::control::ifgreater::ifgreater),
-        );
-        self.funcs.insert(
-            String::from("iflonger"),
-            FuncValue::NativeCondFnError(functions::control::iflonger::iflonger),
-        );
-        self.funcs.insert(
-            String::from("select"),
-            FuncValue::NativeCondFnError(functions::control::select::select),
-        );
-        self.funcs.insert(
-            String::from("and"),
-            FuncValue::NativeCondFnError(functions::control::and::and),
-        );
-        self.funcs.insert(
-            String::from("or"),
-            FuncValue::NativeCondFnError(functions::control::or::or),
-        );
-        self.funcs.insert(
-            String::from("xor"),
-            FuncValue::NativeCondFnError(functions::control::xor::xor),
-        );
-        self.funcs.insert(
-            String::from("not"),
-            FuncValue::NativeCondFnError(functions::control::not::not),
-        );
-
-        self.funcs.insert(
-            String::from("crlf"),
-            FuncValue::NativeCondFnError(functions::str::constants::crlf),
-        );
-        self.funcs.insert(
-            String::from("tab"),
-            FuncValue::NativeCondFnError(functions::str::constants::tab),
-        );
-        self.funcs.insert(
-            String::from("noop"),
-            FuncValue::NativeCondFnError(functions::str::constants::noop),
-        );
+/* the table of functions available to a program.  Only holds plain function
+ * pointers so a single registry can be shared between any number of threads
+ * and evaluations */
+#[derive(Clone, Debug)]
+pub struct Registry {
+    builtins: &'static [(&'static str, FuncValue)],
+    /* user additions, consulted before the builtins */
+    funcs: HashMap<String, FuncValue>,
+}
 
-        self.funcs.insert(
-            String::from("upper"),
-            FuncValue::NativeCondFnError(functions::str::case::upper),
-        );
-        self.funcs.insert(
-            String::from("lower"),
-            FuncValue::NativeCondFnError(functions::str::case::lower),
-        );
-        self.funcs.insert(
-            String::from("firstalphachar"),
-            FuncValue::NativeCondFnError(functions::str::case::firstalphachar),
-        );
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        self.funcs.insert(
-            String::from("len"),
-            FuncValue::NativeCondFnError(functions::str::size::len),
-        );
-        self.funcs.insert(
-            String::from("longer"),
-            FuncValue::NativeCondFnError(functions::str::size::longer),
-        );
+impl Registry {
+    /// Constructs a new `Registry` containing all the built-in functions
+    ///
+    /// The built-in functions are a static table so this does not allocate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::Registry;
+    /// let registry = Registry::new();
+    /// assert!(registry.get("add").is_some());
+    /// ```
+    pub fn new() -> Self {
+        Registry {
+            builtins: &BUILTINS,
+            funcs: HashMap::new(),
+        }
+    }
 
-        self.funcs.insert(
-            String::from("stripprefix"),
-            FuncValue::NativeCondFnError(functions::str::modify::stripprefix),
-        );
-        self.funcs.insert(
-            String::from("swapprefix"),
-            FuncValue::NativeCondFnError(functions::str::modify::swapprefix),
-        );
-        self.funcs.insert(
-            String::from("cut"),
-            FuncValue::NativeCondFnError(functions::str::modify::cut),
-        );
-        self.funcs.insert(
-            String::from("left"),
-            FuncValue::NativeCondFnError(functions::str::modify::left),
-        );
-        self.funcs.insert(
-            String::from("num"),
-            FuncValue::NativeCondFnError(functions::str::format::num),
-        );
+    /// Adds or replaces the function called `name`.  Additions take
+    /// precedence over the built-in function of the same name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::{FuncValue, Registry};
+    /// fn hello(_args: Vec<String>) -> String {
+    ///     String::from("hello")
+    /// }
+    /// let mut registry = Registry::new();
+    /// registry.insert("hello", FuncValue::NativeFn(hello));
+    /// assert!(registry.get("hello").is_some());
+    /// ```
+    pub fn insert(&mut self, name: &str, func: FuncValue) {
+        self.funcs.insert(String::from(name), func);
+    }
 
-        self.funcs.insert(
-            String::from("meta"),
-            FuncValue::NativeEnvFnError(Environment::meta_value),
-        );
-        self.funcs.insert(
-            String::from("meta_sep"),
-            FuncValue::NativeEnvFnError(Environment::meta_sep_value),
-        );
-        self.funcs.insert(
-            String::from("meta_num"),
-            FuncValue::NativeEnvFnError(Environment::meta_num_value),
-        );
-        self.funcs.insert(
-            String::from("meta_test"),
-            FuncValue::NativeEnvFnError(Environment::meta_test_value),
-        );
-        self.funcs.insert(
-            String::from("get"),
-            FuncValue::NativeEnvFnError(Environment::get_value),
-        );
-        self.funcs.insert(
-            String::from("put"),
-            FuncValue::NativeEnvFnError(Environment::put_value),
-        );
-        self.funcs.insert(
-            String::from("puts"),
-            FuncValue::NativeEnvFnError(Environment::puts_value),
-        );
+    /* whether name was added with insert(), such functions also take
+     * precedence over those of the dialect */
+    pub(crate) fn is_inserted(&self, name: &str) -> bool {
+        self.funcs.contains_key(name)
+    }
 
-        self.funcs.insert(
-            String::from("year"),
-            FuncValue::NativeCondFnError(functions::str::datetime::year),
-        );
+    /// Retrieves the function called `name`
+    pub fn get(&self, name: &str) -> Option<&FuncValue> {
+        self.funcs.get(name).or_else(|| {
+            self.builtins
+                .binary_search_by(|(builtin, _)| (*builtin).cmp(name))
+                .ok()
+                .map(|i| &self.builtins[i].1)
+        })
     }
+}
 
+/* everything is a string... */
+/* like a register file in a cpu, but with strings!
+ * Only holds the per-evaluation state, the functions are borrowed from a
+ * (possibly shared) `Registry` */
+/// The state of one evaluation: the metadata of the track and the variables
+/// set so far.
+///
+/// An `Environment` borrows its [`Registry`] and the data it is given,
+/// one made with [`Environment::new`] is an `Environment<'static>`.
+#[derive(Clone)]
+pub struct Environment<'a> {
+    vars: RefCell<HashMap<String, String>>,
+    metadata: Cow<'a, HashMap<String, Vec<String>>>,
+    /* technical information about the stream, kept apart from the tags */
+    info: Cow<'a, HashMap<String, String>>,
+    /* which value %<field>% currently refers to */
+    multi: HashMap<String, usize>,
+    remap: Cow<'a, Remapping>,
+    playlist: Option<&'a Playlist>,
+    position: Option<ListPosition>,
+    playback: Option<PlaybackState>,
+    /* the tracks the aggregate functions work over */
+    group: Option<&'a [Environment<'a>]>,
+    /* how field and function values are made safe for file names */
+    path: Option<&'a PathMode>,
+    /* whether the program is run to change the tags, see Program::run_edit */
+    editing: bool,
+    funcs: Cow<'a, Registry>,
+}
+
+impl<'a> Environment<'a> {
     /// Constructs a new `Environment`
     ///
     /// # Examples
@@ -247,13 +364,228 @@ impl Environment {
     /// let env = Environment::new(metadata);
     /// ```
     pub fn new(metadata: HashMap<String, Vec<String>>) -> Self {
-        let mut env = Environment {
+        Environment {
             vars: RefCell::new(HashMap::new()),
-            metadata: metadata.clone(),
-            funcs: HashMap::new(),
-        };
-        Self::add_default_functions(&mut env);
-        env
+            metadata: Cow::Owned(metadata),
+            info: Cow::Owned(HashMap::new()),
+            multi: HashMap::new(),
+            remap: Cow::Owned(Remapping::new()),
+            playlist: None,
+            position: None,
+            playback: None,
+            group: None,
+            path: None,
+            editing: false,
+            funcs: Cow::Owned(Registry::new()),
+        }
+    }
+
+    /// Constructs a new `Environment` using the functions from `registry`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::{Environment, Registry};
+    /// # use std::collections::HashMap;
+    /// let registry = Registry::new();
+    /// let env = Environment::with_registry(HashMap::new(), &registry);
+    /// ```
+    pub fn with_registry(metadata: HashMap<String, Vec<String>>, registry: &'a Registry) -> Self {
+        Environment {
+            vars: RefCell::new(HashMap::new()),
+            metadata: Cow::Owned(metadata),
+            info: Cow::Owned(HashMap::new()),
+            multi: HashMap::new(),
+            remap: Cow::Owned(Remapping::new()),
+            playlist: None,
+            position: None,
+            playback: None,
+            group: None,
+            path: None,
+            editing: false,
+            funcs: Cow::Borrowed(registry),
+        }
+    }
+
+    /// Constructs a new `Environment` that borrows the metadata and technical
+    /// information of `track` and the functions.  Nothing is copied so this
+    /// is the cheapest way to evaluate a program for many different tracks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::{Environment, Registry};
+    /// # use std::collections::HashMap;
+    /// let registry = Registry::new();
+    /// let metadata = HashMap::new();
+    /// let env = Environment::borrowed(&metadata, &registry);
+    /// ```
+    pub fn borrowed<T: Track>(track: &'a T, registry: &'a Registry) -> Self {
+        Environment {
+            vars: RefCell::new(HashMap::new()),
+            metadata: Cow::Borrowed(track.metadata()),
+            info: match track.info() {
+                Some(info) => Cow::Borrowed(info),
+                None => Cow::Owned(HashMap::new()),
+            },
+            multi: HashMap::new(),
+            remap: Cow::Owned(Remapping::new()),
+            playlist: None,
+            position: None,
+            playback: None,
+            group: None,
+            path: None,
+            editing: false,
+            funcs: Cow::Borrowed(registry),
+        }
+    }
+
+    /// Sets the technical information (sample rate, bitrate, codec, ...)
+    /// about the stream
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::Environment;
+    /// # use std::collections::HashMap;
+    /// let mut env = Environment::new(HashMap::new());
+    /// let mut info = HashMap::new();
+    /// info.insert(String::from("samplerate"), String::from("44100"));
+    /// env.set_info(info);
+    /// assert_eq!(env.get_variable("samplerate").val, "44100");
+    /// ```
+    pub fn set_info(&mut self, info: HashMap<String, String>) {
+        self.info = Cow::Owned(info);
+    }
+
+    /// Sets the table of fallbacks used when looking up fields
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::Environment;
+    /// # use titleformat_rs::remap::Remapping;
+    /// # use std::collections::HashMap;
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert(String::from("date"), vec![String::from("1999")]);
+    /// let mut remap = Remapping::new();
+    /// remap.alias("year", &["date"]);
+    /// let mut env = Environment::new(metadata);
+    /// env.set_remapping(&remap);
+    /// assert_eq!(env.get_variable("year").val, "1999");
+    /// ```
+    pub fn set_remapping(&mut self, remap: &'a Remapping) {
+        self.remap = Cow::Borrowed(remap);
+    }
+
+    /// Sets the playlist the track is formatted as part of
+    pub fn set_playlist(&mut self, playlist: &'a Playlist) {
+        self.playlist = Some(playlist);
+    }
+
+    /// The playlist the track is formatted as part of, if any
+    pub fn playlist(&self) -> Option<&Playlist> {
+        self.playlist
+    }
+
+    /// Sets the 1-based `index` of the track in a list of `total` tracks
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::Environment;
+    /// # use std::collections::HashMap;
+    /// let mut env = Environment::new(HashMap::new());
+    /// env.set_list_position(3, 12);
+    /// assert_eq!(env.get_variable("list_index").val, "03");
+    /// assert_eq!(env.get_variable("list_total").val, "12");
+    /// ```
+    pub fn set_list_position(&mut self, index: usize, total: usize) {
+        self.position = Some(ListPosition { index, total });
+    }
+
+    /// The position of the track in a list, if any
+    pub fn list_position(&self) -> Option<ListPosition> {
+        self.position
+    }
+
+    /// Sets the state of the player
+    pub fn set_playback(&mut self, state: PlaybackState) {
+        self.playback = Some(state);
+    }
+
+    /// The state of the player, if any
+    pub fn playback(&self) -> Option<PlaybackState> {
+        self.playback
+    }
+
+    /// Sets the group of tracks the aggregate functions (`$group_sum()`,
+    /// `%group_length%`, ...) work over
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::environment::Environment;
+    /// # use std::collections::HashMap;
+    /// let tracks: Vec<Environment> = ["3", "4"]
+    ///     .iter()
+    ///     .map(|n| {
+    ///         let mut metadata = HashMap::new();
+    ///         metadata.insert(String::from("n"), vec![String::from(*n)]);
+    ///         Environment::new(metadata)
+    ///     })
+    ///     .collect();
+    /// let mut env = Environment::new(HashMap::new());
+    /// env.set_group(&tracks);
+    /// assert_eq!(env.get_variable("group_count").val, "2");
+    /// ```
+    pub fn set_group(&mut self, group: &'a [Environment<'a>]) {
+        self.group = Some(group);
+    }
+
+    /// The group of tracks being aggregated over, if any
+    pub fn group(&self) -> Option<&[Environment<'a>]> {
+        self.group
+    }
+
+    /// Sets how the values of fields and functions are sanitized for use in
+    /// file and folder names
+    pub fn set_path_mode(&mut self, mode: &'a PathMode) {
+        self.path = Some(mode);
+    }
+
+    /// How the values are sanitized for file names, if they are
+    pub fn path_mode(&self) -> Option<&'a PathMode> {
+        self.path
+    }
+
+    /* lets the foobar2000 functions that change the tags be called */
+    pub(crate) fn set_editing(&mut self, editing: bool) {
+        self.editing = editing;
+    }
+
+    pub(crate) fn is_editing(&self) -> bool {
+        self.editing
+    }
+
+    /// The metadata of the track, including the changes made by the
+    /// program so far
+    pub fn metadata(&self) -> &HashMap<String, Vec<String>> {
+        &self.metadata
+    }
+
+    /* scripts setting tags edit a copy, borrowed metadata is never
+     * touched */
+    pub(crate) fn metadata_mut(&mut self) -> &mut HashMap<String, Vec<String>> {
+        self.metadata.to_mut()
+    }
+
+    /// Retrieves the technical information field `key`
+    pub fn get_info(&self, key: &str) -> Value {
+        match self.info.get(key) {
+            Some(v) => value_string(v, true),
+            None => value_string("?", false),
+        }
     }
 
     fn put_value(&self, args: Vec<Value>) -> Result<Value, Error> {
@@ -311,12 +643,31 @@ impl Environment {
         }
     }
 
+    /* technical information and the context the track is formatted in come
+     * first so tags cannot shadow them, then the fields derived from other
+     * fields, then the remapped fields and finally the tags themselves */
     pub fn get_variable(&self, key: &str) -> Value {
-        self.meta_i(key, 0)
+        functions::info::field(self, key)
+            .or_else(|| playlist::field(self, key))
+            .or_else(|| playback::field(self, key))
+            .or_else(|| functions::aggregate::field(self, key))
+            .or_else(|| functions::derived::field(self, key))
+            .or_else(|| self.remap.lookup(key, &|k| self.meta_i(k, 0)))
+            .unwrap_or_else(|| self.meta_i(key, 0))
+    }
+
+    /* %<key>% is the currently selected value of a multi-value field */
+    pub fn get_multi_variable(&self, key: &str) -> Value {
+        self.meta_i(key, self.multi.get(key).copied().unwrap_or(0))
+    }
+
+    /* selects which value %<key>% refers to */
+    pub(crate) fn select_value(&mut self, key: &str, i: usize) {
+        self.multi.insert(String::from(key), i);
     }
 
     /* gets the ith key from the metadata */
-    fn meta_i(&self, key: &str, i: usize) -> Value {
+    pub(crate) fn meta_i(&self, key: &str, i: usize) -> Value {
         match self.metadata.get(key) {
             Some(v) => {
                 if i >= v.len() {
@@ -358,7 +709,7 @@ impl Environment {
         }
     }
 
-    fn meta_num(&self, key: &str) -> usize {
+    pub(crate) fn meta_num(&self, key: &str) -> usize {
         match self.metadata.get(key) {
             Some(v) => v.len(),
             None => 0,
@@ -439,7 +790,7 @@ impl Environment {
     }
 
     pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
-        let f = { self.funcs.get(name) };
+        let f = self.funcs.get(name);
         match f {
             Some(func_val) => match func_val {
                 FuncValue::NativeFn(func) => {
@@ -528,6 +879,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_builtins_sorted() {
+        assert!(BUILTINS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_registry_override() {
+        fn four(_args: Vec<String>) -> String {
+            String::from("four")
+        }
+        let mut registry = Registry::new();
+        registry.insert("add", FuncValue::NativeFn(four));
+        registry.insert("four", FuncValue::NativeFn(four));
+        let env = Environment::with_registry(HashMap::new(), &registry);
+        assert_eq!(env.call("add", vec![]).unwrap(), value_string("four", true));
+        assert_eq!(
+            env.call("four", vec![]).unwrap(),
+            value_string("four", true)
+        );
+        assert_eq!(
+            env.call(
+                "sub",
+                vec![value_string("4", true), value_string("1", true)]
+            )
+            .unwrap(),
+            value_string("3", true)
+        );
+    }
+
     #[test]
     fn test_call_unknown() {
         let env = Environment::new(HashMap::new());
@@ -648,6 +1028,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_multi_variable() {
+        let mut m = HashMap::new();
+        m.insert(
+            String::from("a"),
+            vec![String::from("0"), String::from("1")],
+        );
+        let mut env = Environment::new(m);
+        assert_eq!(env.get_multi_variable("a"), value_string("0", true));
+        env.select_value("a", 1);
+        assert_eq!(env.get_multi_variable("a"), value_string("1", true));
+        assert_eq!(env.get_variable("a"), value_string("0", true));
+        env.select_value("a", 2);
+        assert_eq!(env.get_multi_variable("a"), value_string("?", false));
+    }
+
+    #[test]
+    fn test_info_not_shadowed() {
+        let mut m = HashMap::new();
+        m.insert(String::from("bitrate"), vec![String::from("tag")]);
+        m.insert(String::from("artist"), vec![String::from("tag")]);
+        let mut env = Environment::new(m);
+        assert_eq!(env.get_variable("bitrate"), value_string("?", false));
+        let mut info = HashMap::new();
+        info.insert(String::from("bitrate"), String::from("320"));
+        info.insert(String::from("artist"), String::from("info"));
+        env.set_info(info);
+        assert_eq!(env.get_variable("bitrate"), value_string("320", true));
+        assert_eq!(env.get_variable("artist"), value_string("tag", true));
+    }
+
     #[test]
     fn test_meta_test() {
         let mut m = HashMap::new();
@@ -0,0 +1,310 @@
+use crate::environment::value_string;
+use crate::environment::{Environment, Value};
+use crate::functions::derived::{format_time, length_seconds};
+use crate::functions::num::to_int;
+use crate::types::Error;
+use crate::types::Error::*;
+
+use std::cmp::Ordering;
+
+/* fields describing the whole group of tracks being formatted:
+ * %group_count%, %group_length%, %group_length_seconds% and %group_filesize%.
+ * Without a group these are looked up as tags like any other field. */
+pub fn field(env: &Environment, key: &str) -> Option<Value> {
+    let group = match key {
+        "group_count" | "group_length" | "group_length_seconds" | "group_filesize" => {
+            env.group()?
+        }
+        _ => return None,
+    };
+    Some(match key {
+        "group_count" => value_string(&group.len().to_string(), true),
+        "group_filesize" => sum(group, "filesize"),
+        _ => {
+            let lengths: Vec<f64> = group.iter().filter_map(length_seconds).collect();
+            if lengths.is_empty() {
+                value_string("?", false)
+            } else {
+                let total = lengths.iter().sum::<f64>().round() as u64;
+                if key == "group_length" {
+                    value_string(&format_time(total), true)
+                } else {
+                    value_string(&total.to_string(), true)
+                }
+            }
+        }
+    })
+}
+
+/* the values of field for each track of the group that has it */
+fn values(group: &[Environment], field: &str) -> Vec<String> {
+    group
+        .iter()
+        .map(|env| env.get_variable(field))
+        .filter(|v| v.cond)
+        .map(|v| v.val)
+        .collect()
+}
+
+fn sum(group: &[Environment], field: &str) -> Value {
+    let values = values(group, field);
+    if values.is_empty() {
+        return value_string("?", false);
+    }
+    let total = values
+        .iter()
+        .fold(0i64, |acc, v| acc.saturating_add(to_int(v)));
+    value_string(&total.to_string(), true)
+}
+
+fn is_number(s: &str) -> bool {
+    let s = s.trim();
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/* numbers are compared numerically, anything else as text */
+fn extreme(group: &[Environment], field: &str, wanted: Ordering) -> Value {
+    let values = values(group, field);
+    let numeric = values.iter().all(|v| is_number(v));
+    values
+        .into_iter()
+        .reduce(|best, v| {
+            let ord = if numeric {
+                to_int(&v).cmp(&to_int(&best))
+            } else {
+                v.cmp(&best)
+            };
+            if ord == wanted {
+                v
+            } else {
+                best
+            }
+        })
+        .map_or(value_string("?", false), |v| value_string(&v, true))
+}
+
+fn field_arg(name: &str, args: &[Value]) -> Result<String, Error> {
+    match args.len() {
+        1 => Ok(args[0].val.clone()),
+        n => Err(InvalidNativeFunctionArgs(String::from(name), n)),
+    }
+}
+
+/*
+ * $group_sum(field)
+ * Returns the sum of the numeric values of field over all the tracks of the
+ * group.
+ * Example: $group_sum(filesize) → "123456789"
+ */
+pub fn group_sum(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    let field = field_arg("group_sum", &args)?;
+    Ok(sum(env.group().unwrap_or(&[]), &field))
+}
+
+/*
+ * $group_min(field)
+ * Returns the smallest value of field in the group.
+ * Example: $group_min(date) → "1999"
+ */
+pub fn group_min(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    let field = field_arg("group_min", &args)?;
+    Ok(extreme(env.group().unwrap_or(&[]), &field, Ordering::Less))
+}
+
+/*
+ * $group_max(field)
+ * Returns the largest value of field in the group.
+ * Example: $group_max(date) → "2004"
+ */
+pub fn group_max(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    let field = field_arg("group_max", &args)?;
+    Ok(extreme(
+        env.group().unwrap_or(&[]),
+        &field,
+        Ordering::Greater,
+    ))
+}
+
+/*
+ * $group_distinct(field)
+ * Returns the number of different values of field in the group.
+ * Example: $group_distinct(artist) → "3"
+ */
+pub fn group_distinct(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    let field = field_arg("group_distinct", &args)?;
+    let mut values = values(env.group().unwrap_or(&[]), &field);
+    values.sort();
+    values.dedup();
+    Ok(value_string(&values.len().to_string(), !values.is_empty()))
+}
+
+/*
+ * $group_common(field)
+ * $group_common(field,various)
+ * Returns the value of field if it is the same for all the tracks of the
+ * group, otherwise various which defaults to "Various".
+ * Example: $group_common(album artist,Various Artists) → "Various Artists"
+ */
+pub fn group_common(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    let (field, various) = match args.len() {
+        1 => (args[0].val.clone(), String::from("Various")),
+        2 => (args[0].val.clone(), args[1].val.clone()),
+        n => return Err(InvalidNativeFunctionArgs(String::from("group_common"), n)),
+    };
+    let group = env.group().unwrap_or(&[]);
+    let mut values = group.iter().map(|env| env.get_variable(&field));
+    let Some(first) = values.next() else {
+        return Ok(value_string("?", false));
+    };
+    /* a track missing the field is different to one that has it */
+    if values.all(|v| v == first) {
+        Ok(first)
+    } else {
+        Ok(value_string(&various, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn envs(tracks: &[&[(&str, &str)]]) -> Vec<Environment<'static>> {
+        tracks
+            .iter()
+            .map(|tags| {
+                let mut env = Environment::new(
+                    tags.iter()
+                        .map(|(k, v)| (String::from(*k), vec![String::from(*v)]))
+                        .collect(),
+                );
+                env.set_info(
+                    tags.iter()
+                        .map(|(k, v)| (String::from(*k), String::from(*v)))
+                        .collect(),
+                );
+                env
+            })
+            .collect()
+    }
+
+    fn arg(s: &str) -> Vec<Value> {
+        vec![value_string(s, true)]
+    }
+
+    #[test]
+    fn wrong_n_arguments() {
+        let env = Environment::new(HashMap::new());
+        assert_eq!(
+            group_sum(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("group_sum"), 0)
+        );
+        assert_eq!(
+            group_min(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("group_min"), 0)
+        );
+        assert_eq!(
+            group_max(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("group_max"), 0)
+        );
+        assert_eq!(
+            group_distinct(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("group_distinct"), 0)
+        );
+        assert_eq!(
+            group_common(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("group_common"), 0)
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        let group = envs(&[
+            &[("length_seconds", "100.4"), ("filesize", "1000")],
+            &[("length_seconds", "3600"), ("filesize", "24")],
+            &[],
+        ]);
+        let mut env = Environment::new(HashMap::new());
+        env.set_group(&group);
+        assert_eq!(field(&env, "group_count"), Some(value_string("3", true)));
+        assert_eq!(
+            field(&env, "group_length"),
+            Some(value_string("1:01:40", true))
+        );
+        assert_eq!(
+            field(&env, "group_length_seconds"),
+            Some(value_string("3700", true))
+        );
+        assert_eq!(
+            field(&env, "group_filesize"),
+            Some(value_string("1024", true))
+        );
+        assert_eq!(field(&env, "artist"), None);
+    }
+
+    #[test]
+    fn test_no_group() {
+        let env = Environment::new(HashMap::new());
+        assert_eq!(field(&env, "group_count"), None);
+        assert_eq!(field(&env, "group_length"), None);
+        assert_eq!(group_sum(&env, arg("a")).unwrap(), value_string("?", false));
+        /* tags of the same name are not shadowed */
+        let mut env = envs(&[&[("group_count", "7")]]).remove(0);
+        assert_eq!(env.get_variable("group_count"), value_string("7", true));
+        let group = envs(&[&[], &[]]);
+        env.set_group(&group);
+        assert_eq!(env.get_variable("group_count"), value_string("2", true));
+    }
+
+    #[test]
+    fn test_sum_min_max() {
+        let group = envs(&[&[("n", "10")], &[("n", "9")], &[("n", "-2")], &[]]);
+        let mut env = Environment::new(HashMap::new());
+        env.set_group(&group);
+        assert_eq!(group_sum(&env, arg("n")).unwrap(), value_string("17", true));
+        assert_eq!(group_min(&env, arg("n")).unwrap(), value_string("-2", true));
+        assert_eq!(group_max(&env, arg("n")).unwrap(), value_string("10", true));
+
+        let group = envs(&[&[("d", "1999-10")], &[("d", "2004")], &[("d", "1999")]]);
+        env.set_group(&group);
+        assert_eq!(
+            group_min(&env, arg("d")).unwrap(),
+            value_string("1999", true)
+        );
+        assert_eq!(
+            group_max(&env, arg("d")).unwrap(),
+            value_string("2004", true)
+        );
+    }
+
+    #[test]
+    fn test_distinct_common() {
+        let group = envs(&[&[("a", "x")], &[("a", "y")], &[("a", "x")]]);
+        let mut env = Environment::new(HashMap::new());
+        env.set_group(&group);
+        assert_eq!(
+            group_distinct(&env, arg("a")).unwrap(),
+            value_string("2", true)
+        );
+        assert_eq!(
+            group_common(&env, arg("a")).unwrap(),
+            value_string("Various", true)
+        );
+        assert_eq!(
+            group_common(
+                &env,
+                vec![value_string("a", true), value_string("VA", true)]
+            )
+            .unwrap(),
+            value_string("VA", true)
+        );
+
+        let group = envs(&[&[("a", "x")], &[("a", "x")]]);
+        env.set_group(&group);
+        assert_eq!(
+            group_common(&env, arg("a")).unwrap(),
+            value_string("x", true)
+        );
+    }
+}
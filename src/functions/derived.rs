@@ -0,0 +1,220 @@
+use crate::environment::value_string;
+use crate::environment::{Environment, Value};
+use crate::functions::num::to_int;
+
+/* fields that are not tags but are derived from the tags and technical
+ * information of a track, e.g. %length% or %filename% */
+pub fn field(env: &Environment, key: &str) -> Option<Value> {
+    match key {
+        "length" => Some(length(env, format_time)),
+        "length_seconds" => Some(length(env, |secs| secs.to_string())),
+        "path" => Some(path(env)),
+        "filename" => Some(path_part(env, |p| split_ext(file_name(p)).0)),
+        "filename_ext" => Some(path_part(env, file_name)),
+        "ext" => Some(path_part(env, |p| split_ext(file_name(p)).1)),
+        "directoryname" => Some(path_part(env, directory_name)),
+        "tracknumber" => Some(tracknumber(env)),
+        "title" => Some(title(env)),
+        _ => None,
+    }
+}
+
+/* formats a number of seconds as m:ss or h:mm:ss */
+pub fn format_time(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+/* the length of the track in seconds, either from the number of samples and
+ * the samplerate or directly from a number of seconds */
+pub fn length_seconds(env: &Environment) -> Option<f64> {
+    let samples = env.get_info("length_samples");
+    let rate = env.get_info("samplerate");
+    if samples.cond && rate.cond {
+        let rate = to_int(&rate.val);
+        if rate > 0 {
+            return Some(to_int(&samples.val) as f64 / rate as f64);
+        }
+    }
+    let secs = env.get_info("length_seconds");
+    if secs.cond {
+        return secs.val.trim().parse::<f64>().ok().filter(|s| *s >= 0.);
+    }
+    None
+}
+
+fn length(env: &Environment, format: fn(u64) -> String) -> Value {
+    match length_seconds(env) {
+        Some(secs) => value_string(&format(secs.round() as u64), true),
+        None => value_string("?", false),
+    }
+}
+
+/* the location of the file is technical information but may also have been
+ * provided as a tag */
+fn path(env: &Environment) -> Value {
+    let path = env.get_info("path");
+    if path.cond {
+        path
+    } else {
+        env.meta_i("path", 0)
+    }
+}
+
+fn path_part(env: &Environment, part: fn(&str) -> &str) -> Value {
+    let path = path(env);
+    if path.cond {
+        value_string(part(&path.val), true)
+    } else {
+        path
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+fn file_name(path: &str) -> &str {
+    match path.rfind(is_separator) {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+fn directory_name(path: &str) -> &str {
+    match path.rfind(is_separator) {
+        Some(i) => file_name(&path[..i]),
+        None => "",
+    }
+}
+
+/* splits "name.ext" into ("name", "ext"), a leading dot is not an extension */
+fn split_ext(file_name: &str) -> (&str, &str) {
+    match file_name.rfind('.') {
+        Some(i) if i > 0 => (&file_name[..i], &file_name[i + 1..]),
+        _ => (file_name, ""),
+    }
+}
+
+/* %tracknumber% is padded to at least two digits, "3/12" becomes "03" */
+fn tracknumber(env: &Environment) -> Value {
+    let track = env.meta_i("tracknumber", 0);
+    if !track.cond {
+        return track;
+    }
+    let number = track.val.split('/').next().unwrap_or("").trim();
+    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+        value_string(&format!("{:0>2}", number), true)
+    } else {
+        track
+    }
+}
+
+/* %title% falls back to the file name */
+fn title(env: &Environment) -> Value {
+    let title = env.meta_i("title", 0);
+    if title.cond {
+        return title;
+    }
+    let name = path_part(env, |p| split_ext(file_name(p)).0);
+    if name.cond {
+        name
+    } else {
+        title
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(meta: &[(&str, &str)], info: &[(&str, &str)]) -> Environment<'static> {
+        let mut env = Environment::new(
+            meta.iter()
+                .map(|(k, v)| (String::from(*k), vec![String::from(*v)]))
+                .collect(),
+        );
+        env.set_info(
+            info.iter()
+                .map(|(k, v)| (String::from(*k), String::from(*v)))
+                .collect::<HashMap<_, _>>(),
+        );
+        env
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "0:00");
+        assert_eq!(format_time(65), "1:05");
+        assert_eq!(format_time(3723), "1:02:03");
+    }
+
+    #[test]
+    fn test_length() {
+        let e = env(
+            &[],
+            &[("length_samples", "441000"), ("samplerate", "44100")],
+        );
+        assert_eq!(field(&e, "length"), Some(value_string("0:10", true)));
+        assert_eq!(field(&e, "length_seconds"), Some(value_string("10", true)));
+
+        let e = env(&[], &[("length_seconds", "215.6")]);
+        assert_eq!(field(&e, "length"), Some(value_string("3:36", true)));
+        assert_eq!(field(&e, "length_seconds"), Some(value_string("216", true)));
+
+        let e = env(&[], &[]);
+        assert_eq!(field(&e, "length"), Some(value_string("?", false)));
+    }
+
+    #[test]
+    fn test_path() {
+        let e = env(&[], &[("path", "/music/Artist/Album/01 Song.flac")]);
+        assert_eq!(field(&e, "filename"), Some(value_string("01 Song", true)));
+        assert_eq!(
+            field(&e, "filename_ext"),
+            Some(value_string("01 Song.flac", true))
+        );
+        assert_eq!(field(&e, "ext"), Some(value_string("flac", true)));
+        assert_eq!(
+            field(&e, "directoryname"),
+            Some(value_string("Album", true))
+        );
+
+        let e = env(&[("path", "C:\\Music\\.hidden")], &[]);
+        assert_eq!(field(&e, "filename"), Some(value_string(".hidden", true)));
+        assert_eq!(
+            field(&e, "directoryname"),
+            Some(value_string("Music", true))
+        );
+
+        let e = env(&[], &[]);
+        assert_eq!(field(&e, "filename"), Some(value_string("?", false)));
+    }
+
+    #[test]
+    fn test_tracknumber() {
+        let e = env(&[("tracknumber", "3/12")], &[]);
+        assert_eq!(field(&e, "tracknumber"), Some(value_string("03", true)));
+        let e = env(&[("tracknumber", "123")], &[]);
+        assert_eq!(field(&e, "tracknumber"), Some(value_string("123", true)));
+        let e = env(&[("tracknumber", "A1")], &[]);
+        assert_eq!(field(&e, "tracknumber"), Some(value_string("A1", true)));
+        let e = env(&[], &[]);
+        assert_eq!(field(&e, "tracknumber"), Some(value_string("?", false)));
+    }
+
+    #[test]
+    fn test_title() {
+        let e = env(&[("title", "Song")], &[("path", "/a/b.mp3")]);
+        assert_eq!(field(&e, "title"), Some(value_string("Song", true)));
+        let e = env(&[], &[("path", "/a/b.mp3")]);
+        assert_eq!(field(&e, "title"), Some(value_string("b", true)));
+        let e = env(&[], &[]);
+        assert_eq!(field(&e, "title"), Some(value_string("?", false)));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::dialect::LazyFn;
+use crate::environment::{Environment, Value};
+use crate::program::Program;
+use crate::types::Error;
+use crate::types::Error::*;
+use crate::types::Expr;
+
+/* the functions changing the tags, sorted by name so lookups can use a
+ * binary search.  foobar2000 has them in run_edit only, Picard always */
+pub(crate) static FUNCTIONS: [(&str, LazyFn); 3] =
+    [("append", append), ("set", set), ("unset", unset)];
+
+/* a value that is true when it is not empty */
+pub(crate) fn text(s: String) -> Value {
+    Value {
+        cond: !s.is_empty(),
+        val: s,
+    }
+}
+
+pub(crate) fn check(name: &str, args: &[Vec<Expr>], min: usize, max: usize) -> Result<(), Error> {
+    if args.len() < min || args.len() > max {
+        return Err(InvalidNativeFunctionArgs(String::from(name), args.len()));
+    }
+    Ok(())
+}
+
+/* evaluates the ith argument, missing optional arguments are empty */
+pub(crate) fn arg(
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+    i: usize,
+) -> Result<String, Error> {
+    match args.get(i) {
+        Some(exprs) => Ok(program.evaluate_exprs(env, exprs)?.val),
+        None => Ok(String::new()),
+    }
+}
+
+/* sets all the values of a tag, no values removes it */
+pub(crate) fn set_values(env: &mut Environment, name: &str, values: Vec<String>) {
+    if values.is_empty() {
+        if env.metadata().contains_key(name) {
+            env.metadata_mut().remove(name);
+        }
+    } else {
+        env.metadata_mut().insert(String::from(name), values);
+    }
+}
+
+/*
+ * $set(name,value)
+ * Sets the tag name to the single value, an empty value removes it.
+ */
+pub(crate) fn set(
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+) -> Result<Value, Error> {
+    check("set", args, 2, 2)?;
+    let name = arg(program, env, args, 0)?;
+    let value = arg(program, env, args, 1)?;
+    let values = if value.is_empty() {
+        vec![]
+    } else {
+        vec![value]
+    };
+    set_values(env, &name, values);
+    Ok(text(String::new()))
+}
+
+/*
+ * $append(name,value)
+ * Adds value to the values of the tag name, an empty value is ignored.
+ */
+pub(crate) fn append(
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+) -> Result<Value, Error> {
+    check("append", args, 2, 2)?;
+    let name = arg(program, env, args, 0)?;
+    let value = arg(program, env, args, 1)?;
+    if !value.is_empty() {
+        env.metadata_mut().entry(name).or_default().push(value);
+    }
+    Ok(text(String::new()))
+}
+
+/*
+ * $unset(name)
+ * Removes the tag name, a trailing * removes all the tags starting with
+ * name.
+ */
+pub(crate) fn unset(
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+) -> Result<Value, Error> {
+    check("unset", args, 1, 1)?;
+    let name = arg(program, env, args, 0)?;
+    match name.strip_suffix('*') {
+        Some(prefix) => {
+            let names: Vec<String> = env
+                .metadata()
+                .keys()
+                .filter(|k| k.starts_with(prefix))
+                .cloned()
+                .collect();
+            for name in names {
+                set_values(env, &name, vec![]);
+            }
+        }
+        None => set_values(env, &name, vec![]),
+    }
+    Ok(text(String::new()))
+}
@@ -0,0 +1,145 @@
+use crate::environment::value_string;
+use crate::environment::{Environment, Value};
+use crate::functions::num::to_int;
+use crate::types::Error;
+use crate::types::Error::*;
+
+/* fields that are always read from the technical information and never from
+ * the tags */
+pub fn field(env: &Environment, key: &str) -> Option<Value> {
+    match key {
+        "channels" => Some(channels_value(env)),
+        "codec" => Some(env.get_info("codec")),
+        "samplerate" | "bitrate" | "filesize" => Some(env.get_info(key)),
+        _ => None,
+    }
+}
+
+fn channels_value(env: &Environment) -> Value {
+    let channels = env.get_info("channels");
+    if !channels.cond {
+        return channels;
+    }
+    let s = match to_int(&channels.val) {
+        1 => String::from("mono"),
+        2 => String::from("stereo"),
+        n => format!("{} ch", n),
+    };
+    value_string(&s, true)
+}
+
+/*
+ * $info(name)
+ * Returns the value of the technical information field called name.
+ * Example: $info(samplerate) → "44100"
+ */
+pub fn info(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    match args.len() {
+        1 => Ok(env.get_info(&args[0].val)),
+        _ => Err(InvalidNativeFunctionArgs(String::from("info"), args.len())),
+    }
+}
+
+/*
+ * $channels()
+ * Returns the number of channels as text: "mono", "stereo" or "N ch".
+ */
+pub fn channels(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    match args.len() {
+        /* $channels() is parsed with a single empty argument */
+        0 | 1 => Ok(channels_value(env)),
+        _ => Err(InvalidNativeFunctionArgs(
+            String::from("channels"),
+            args.len(),
+        )),
+    }
+}
+
+/*
+ * $codec()
+ * Returns the name of the codec of the stream.
+ */
+pub fn codec(env: &Environment, args: Vec<Value>) -> Result<Value, Error> {
+    match args.len() {
+        0 | 1 => Ok(env.get_info("codec")),
+        _ => Err(InvalidNativeFunctionArgs(String::from("codec"), args.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_with_info(info: &[(&str, &str)]) -> Environment<'static> {
+        let mut env = Environment::new(HashMap::new());
+        env.set_info(
+            info.iter()
+                .map(|(k, v)| (String::from(*k), String::from(*v)))
+                .collect(),
+        );
+        env
+    }
+
+    #[test]
+    fn wrong_n_arguments() {
+        let env = env_with_info(&[]);
+        assert_eq!(
+            info(&env, vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("info"), 0)
+        );
+        assert_eq!(
+            channels(&env, vec![value_string("", true), value_string("", true)])
+                .err()
+                .unwrap(),
+            InvalidNativeFunctionArgs(String::from("channels"), 2)
+        );
+        assert_eq!(
+            codec(&env, vec![value_string("", true), value_string("", true)])
+                .err()
+                .unwrap(),
+            InvalidNativeFunctionArgs(String::from("codec"), 2)
+        );
+    }
+
+    #[test]
+    fn test_info() {
+        let env = env_with_info(&[("samplerate", "44100")]);
+        assert_eq!(
+            info(&env, vec![value_string("samplerate", true)]).unwrap(),
+            value_string("44100", true)
+        );
+        assert_eq!(
+            info(&env, vec![value_string("bitrate", true)]).unwrap(),
+            value_string("?", false)
+        );
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(
+            channels(&env_with_info(&[("channels", "1")]), vec![]).unwrap(),
+            value_string("mono", true)
+        );
+        assert_eq!(
+            channels(&env_with_info(&[("channels", "2")]), vec![]).unwrap(),
+            value_string("stereo", true)
+        );
+        assert_eq!(
+            channels(&env_with_info(&[("channels", "6")]), vec![]).unwrap(),
+            value_string("6 ch", true)
+        );
+        assert_eq!(
+            channels(&env_with_info(&[]), vec![]).unwrap(),
+            value_string("?", false)
+        );
+    }
+
+    #[test]
+    fn test_codec() {
+        assert_eq!(
+            codec(&env_with_info(&[("codec", "FLAC")]), vec![]).unwrap(),
+            value_string("FLAC", true)
+        );
+    }
+}
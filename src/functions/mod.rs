@@ -1,3 +1,9 @@
+pub mod aggregate;
 pub mod control;
+pub mod derived;
+pub mod edit;
+pub mod info;
+pub mod mp3tag;
 pub mod num;
+pub mod picard;
 pub mod str;
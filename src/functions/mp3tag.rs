@@ -0,0 +1,384 @@
+use crate::dialect::LazyFn;
+use crate::environment::{Environment, Value};
+use crate::functions::edit::{arg, check, text};
+use crate::functions::num::to_int;
+use crate::path::PathMode;
+use crate::program::Program;
+use crate::types::Error;
+use crate::types::Error::*;
+use crate::types::Expr;
+
+use regex::{Regex, RegexBuilder};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/* how many compiled patterns of $regexp() each thread keeps */
+const REGEX_CACHE_SIZE: usize = 64;
+
+thread_local! {
+    /* a program is usually run for many tracks with the same patterns */
+    static REGEX_CACHE: RefCell<HashMap<(String, bool), Regex>> = RefCell::new(HashMap::new());
+}
+
+/* Mp3tag shows the values of multi-value fields separated by this */
+const JOINER: &str = "\\\\";
+
+/* the functions of the Mp3tag dialect, sorted by name so lookups can use a
+ * binary search */
+pub(crate) static FUNCTIONS: [(&str, LazyFn); 15] = [
+    ("and", and),
+    ("eql", eql),
+    ("geql", geql),
+    ("grtr", grtr),
+    ("if", if_),
+    ("if2", if2),
+    ("leql", leql),
+    ("less", less),
+    ("neql", neql),
+    ("not", not),
+    ("num", num),
+    ("or", or),
+    ("regexp", regexp),
+    ("replace", replace),
+    ("validate", validate),
+];
+
+/* Mp3tag's true is "1" and false is "0" */
+fn boolean(b: bool) -> Value {
+    text(String::from(if b { "1" } else { "0" }))
+}
+
+/* a condition is true when it is neither empty nor 0 */
+fn truthy(s: &str) -> bool {
+    !s.is_empty() && s != "0"
+}
+
+fn condition(program: &Program, env: &mut Environment, exprs: &[Expr]) -> Result<bool, Error> {
+    Ok(truthy(&program.evaluate_exprs(env, exprs)?.val))
+}
+
+/* %name%, field names are case insensitive and missing fields are empty */
+pub(crate) fn variable(env: &Environment, name: &str) -> Value {
+    let metadata = env.metadata();
+    let values = metadata.get(name).or_else(|| {
+        metadata
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    });
+    match values {
+        Some(values) => text(values.join(JOINER)),
+        None => match env.get_variable(name) {
+            value @ Value { cond: true, .. } => text(value.val),
+            _ => text(String::new()),
+        },
+    }
+}
+
+/*
+ * $if(x,y[,z])
+ * Returns y if x is true, otherwise z.  Only the branch taken is evaluated.
+ */
+fn if_(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("if", args, 2, 3)?;
+    if condition(program, env, &args[0])? {
+        program.evaluate_exprs(env, &args[1])
+    } else {
+        Ok(text(arg(program, env, args, 2)?))
+    }
+}
+
+/*
+ * $if2(x,y)
+ * Returns x if it is true, otherwise y.
+ */
+fn if2(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("if2", args, 2, 2)?;
+    let x = arg(program, env, args, 0)?;
+    if truthy(&x) {
+        Ok(text(x))
+    } else {
+        Ok(text(arg(program, env, args, 1)?))
+    }
+}
+
+/*
+ * $and(x,y,...)
+ * Returns 1 if all the arguments are true, otherwise 0.
+ */
+fn and(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    for exprs in args {
+        if !condition(program, env, exprs)? {
+            return Ok(boolean(false));
+        }
+    }
+    Ok(boolean(true))
+}
+
+/*
+ * $or(x,y,...)
+ * Returns 1 if any of the arguments is true, otherwise 0.
+ */
+fn or(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    for exprs in args {
+        if condition(program, env, exprs)? {
+            return Ok(boolean(true));
+        }
+    }
+    Ok(boolean(false))
+}
+
+/*
+ * $not(x)
+ * Returns 0 if x is true, otherwise 1.
+ */
+fn not(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("not", args, 1, 1)?;
+    Ok(boolean(!condition(program, env, &args[0])?))
+}
+
+/* evaluates both arguments of a comparison */
+fn pair(
+    name: &str,
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+) -> Result<(String, String), Error> {
+    check(name, args, 2, 2)?;
+    Ok((arg(program, env, args, 0)?, arg(program, env, args, 1)?))
+}
+
+/* compares two numbers, text counts as 0 */
+fn compare(
+    name: &str,
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+    f: fn(i64, i64) -> bool,
+) -> Result<Value, Error> {
+    let (x, y) = pair(name, program, env, args)?;
+    Ok(boolean(f(to_int(&x), to_int(&y))))
+}
+
+/*
+ * $eql(x,y)
+ * Returns 1 if x and y are the same text, otherwise 0.
+ */
+fn eql(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (x, y) = pair("eql", program, env, args)?;
+    Ok(boolean(x == y))
+}
+
+/*
+ * $neql(x,y)
+ * Returns 1 if x and y are different texts, otherwise 0.
+ */
+fn neql(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (x, y) = pair("neql", program, env, args)?;
+    Ok(boolean(x != y))
+}
+
+/* $grtr(x,y), $geql(x,y), $less(x,y) and $leql(x,y) compare numbers */
+fn grtr(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("grtr", program, env, args, |x, y| x > y)
+}
+
+fn geql(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("geql", program, env, args, |x, y| x >= y)
+}
+
+fn less(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("less", program, env, args, |x, y| x < y)
+}
+
+fn leql(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("leql", program, env, args, |x, y| x <= y)
+}
+
+/*
+ * $num(x,y)
+ * Formats the number x with at least y digits, padded with zeros.  Unlike
+ * foobar2000 the sign does not count as a digit, and anything after the
+ * number is dropped: $num(3/12,2) → 03, $num(-5,3) → -005.
+ */
+fn num(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (x, y) = pair("num", program, env, args)?;
+    let x = to_int(&x);
+    let digits = to_int(&y).max(0) as usize;
+    let sign = if x < 0 { "-" } else { "" };
+    Ok(text(format!(
+        "{}{:0width$}",
+        sign,
+        x.unsigned_abs(),
+        width = digits
+    )))
+}
+
+/*
+ * $replace(x,y1,z1[,y2,z2,...])
+ * Replaces every y1 in x with z1, then every y2 in the result with z2, and
+ * so on.
+ */
+fn replace(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let pairs = args.get(1..).unwrap_or_default().chunks_exact(2);
+    if pairs.len() == 0 || !pairs.remainder().is_empty() {
+        return Err(InvalidNativeFunctionArgs(
+            String::from("replace"),
+            args.len(),
+        ));
+    }
+    let mut s = arg(program, env, args, 0)?;
+    for pair in pairs {
+        let from = program.evaluate_exprs(env, &pair[0])?.val;
+        let to = program.evaluate_exprs(env, &pair[1])?.val;
+        if !from.is_empty() {
+            s = s.replace(&from, &to);
+        }
+    }
+    Ok(text(s))
+}
+
+/*
+ * $regexp(x,expr,replacement[,ignorecase])
+ * Replaces every match of the regular expression expr in x with
+ * replacement, which can refer to the groups as $1, $2, ...  The match is
+ * case insensitive when ignorecase is 1.  As everywhere else, $ has to be
+ * quoted in the replacement: '$1'.
+ */
+fn regexp(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("regexp", args, 3, 4)?;
+    let x = arg(program, env, args, 0)?;
+    let expr = arg(program, env, args, 1)?;
+    let replacement = arg(program, env, args, 2)?;
+    let ignore_case = truthy(&arg(program, env, args, 3)?);
+    let re = compile(expr, ignore_case)?;
+    Ok(text(re.replace_all(&x, replacement.as_str()).into_owned()))
+}
+
+/* compiles a pattern of $regexp() or takes it from the cache */
+fn compile(expr: String, ignore_case: bool) -> Result<Regex, Error> {
+    let key = (expr, ignore_case);
+    if let Some(re) = REGEX_CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+        return Ok(re);
+    }
+    let re = RegexBuilder::new(&key.0)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| PatternError(e.to_string()))?;
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= REGEX_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(key, re.clone());
+    });
+    Ok(re)
+}
+
+/*
+ * $validate(x,c)
+ * Replaces the characters that cannot be part of a file name with c.
+ */
+fn validate(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (x, c) = pair("validate", program, env, args)?;
+    let mut mode = PathMode::windows();
+    mode.set_replacement(&c);
+    Ok(text(mode.sanitize(&x)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use std::collections::HashMap;
+
+    fn run(script: &str, tags: &[(&str, &[&str])]) -> Result<String, Error> {
+        let mut program = Program::new();
+        program.set_dialect(Dialect::Mp3tag);
+        program.parse(script)?;
+        let metadata: HashMap<String, Vec<String>> = tags
+            .iter()
+            .map(|(k, v)| {
+                (
+                    String::from(*k),
+                    v.iter().map(|s| String::from(*s)).collect(),
+                )
+            })
+            .collect();
+        program.run_with_meta(metadata)
+    }
+
+    fn ok(script: &str, tags: &[(&str, &[&str])]) -> String {
+        run(script, tags).unwrap()
+    }
+
+    #[test]
+    fn test_functions_sorted() {
+        assert!(FUNCTIONS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_variables() {
+        let tags: &[(&str, &[&str])] = &[("ARTIST", &["A", "B"]), ("album", &["X"])];
+        assert_eq!(ok("%artist% %ALBUM%", tags), "A\\\\B X");
+        assert_eq!(ok("%missing%", tags), "");
+        assert_eq!(ok("[%album% - ]%title%", tags), "X - ");
+        assert_eq!(ok("[%title% - ]%album%", tags), "X");
+        assert_eq!(ok("[(%title%)]", tags), "");
+    }
+
+    #[test]
+    fn test_control() {
+        let tags: &[(&str, &[&str])] = &[("track", &["0"]), ("title", &["T"])];
+        assert_eq!(ok("$if(%title%,yes,no)", tags), "yes");
+        assert_eq!(ok("$if(%track%,yes,no)", tags), "no");
+        assert_eq!(ok("$if($eql(%title%,T),yes)", tags), "yes");
+        assert_eq!(ok("$if2(%missing%,default)", tags), "default");
+        assert_eq!(ok("$and(1,%title%)$or(0,)$not(0)", tags), "101");
+        assert_eq!(ok("$eql(a,A)$neql(a,A)", tags), "01");
+        assert_eq!(
+            ok("$grtr(10,9)$geql(9,9)$less(x,1)$leql(2,1)", tags),
+            "1110"
+        );
+        /* the foobar2000 functions without a counterpart remain */
+        assert_eq!(ok("$upper(%title%)", tags), "T");
+    }
+
+    #[test]
+    fn test_num() {
+        assert_eq!(ok("$num(3/12,2)", &[]), "03");
+        assert_eq!(ok("$num(-5,3)", &[]), "-005");
+        assert_eq!(ok("$num(123,2)", &[]), "123");
+        assert_eq!(ok("$num(abc,2)", &[]), "00");
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(ok("$replace(abc,a,b,b,c)", &[]), "ccc");
+        assert_eq!(ok("$replace(abc,,x)", &[]), "abc");
+        assert_eq!(
+            run("$replace(abc,a)", &[]),
+            Err(InvalidNativeFunctionArgs(String::from("replace"), 2))
+        );
+    }
+
+    #[test]
+    fn test_regexp() {
+        assert_eq!(
+            ok(
+                "$regexp(%title%,'(.*) \\(feat. (.*)\\)','$1 / $2')",
+                &[("title", &["Song (feat. X)"])]
+            ),
+            "Song / X"
+        );
+        assert_eq!(ok("$regexp(aBA,a,x)", &[]), "xBA");
+        assert_eq!(ok("$regexp(aBA,a,x,1)", &[]), "xBx");
+        assert!(matches!(run("$regexp(a,'(',x)", &[]), Err(PatternError(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(ok("$validate(AC/DC: Live?,_)", &[]), "AC_DC_ Live_");
+        assert_eq!(ok("$validate(a*b,)", &[]), "ab");
+    }
+}
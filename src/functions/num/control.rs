@@ -46,6 +46,13 @@ pub fn gt(args: Vec<Value>) -> Result<Value, Error> {
     Ok(value_string("", a > b))
 }
 
+/* $greater(a,b)
+ * Return True if a > b
+ */
+pub fn greater(args: Vec<Value>) -> Result<Value, Error> {
+    gt(args).map_err(|e| map_err_func_name(e, "greater"))
+}
+
 /* $gte(a,b)
  * Return True if a > b
  */
@@ -93,6 +100,10 @@ mod tests {
             gt(vec![]).err().unwrap(),
             InvalidNativeFunctionArgs(String::from("gt"), 0)
         );
+        assert_eq!(
+            greater(vec![]).err().unwrap(),
+            InvalidNativeFunctionArgs(String::from("greater"), 0)
+        );
         assert_eq!(
             gte(vec![]).err().unwrap(),
             InvalidNativeFunctionArgs(String::from("gte"), 0)
@@ -161,6 +172,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_greater() {
+        assert_eq!(
+            value_string("", false),
+            greater(vec![value_string("1", true), value_string("2", true)])
+                .ok()
+                .unwrap()
+        );
+        assert_eq!(
+            value_string("", true),
+            greater(vec![value_string("2", true), value_string("1", true)])
+                .ok()
+                .unwrap()
+        );
+    }
+
     #[test]
     fn test_gte() {
         assert_eq!(
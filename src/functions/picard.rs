@@ -0,0 +1,667 @@
+use crate::dialect::LazyFn;
+use crate::environment::{Environment, Value};
+use crate::functions::edit::{self, arg, check, set, set_values, text, unset};
+use crate::program::Program;
+use crate::types::Error;
+use crate::types::Error::*;
+use crate::types::Expr;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/* Picard joins the values of multi-value tags with this */
+const JOINER: &str = "; ";
+/* $while gives up after this many loops, as Picard does */
+const MAX_LOOPS: usize = 999;
+
+/* the functions of the Picard dialect, sorted by name so lookups can use a
+ * binary search */
+pub(crate) static FUNCTIONS: [(&str, LazyFn); 26] = [
+    ("and", and),
+    ("append", edit::append),
+    ("copy", copy),
+    ("copymerge", copymerge),
+    ("eq", eq),
+    ("foreach", foreach),
+    ("get", get),
+    ("getmulti", getmulti),
+    ("gt", gt),
+    ("gte", gte),
+    ("if", if_),
+    ("if2", if2),
+    ("join", join),
+    ("lenmulti", lenmulti),
+    ("lt", lt),
+    ("lte", lte),
+    ("map", map),
+    ("ne", ne),
+    ("not", not),
+    ("or", or),
+    ("reverse", reverse),
+    ("set", set),
+    ("slice", slice),
+    ("unique", unique),
+    ("unset", unset),
+    ("while", while_),
+];
+
+/* Picard's true is "1" and false is "" */
+fn boolean(b: bool) -> Value {
+    text(String::from(if b { "1" } else { "" }))
+}
+
+/* the separator argument, "; " unless given */
+fn separator(
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+    i: usize,
+) -> Result<String, Error> {
+    match args.get(i) {
+        Some(_) => arg(program, env, args, i),
+        None => Ok(String::from(JOINER)),
+    }
+}
+
+fn number(s: &str) -> Option<i64> {
+    s.trim().parse().ok()
+}
+
+/* a multi-value argument: the values of the tag for a plain %tag% with the
+ * default separator, otherwise the text split on the separator */
+fn multi(
+    program: &Program,
+    env: &mut Environment,
+    exprs: &[Expr],
+    sep: &str,
+) -> Result<Vec<String>, Error> {
+    if let [Expr::Variable(name)] = exprs {
+        if sep == JOINER {
+            return Ok(env.metadata().get(name).cloned().unwrap_or_default());
+        }
+    }
+    let s = program.evaluate_exprs(env, exprs)?.val;
+    Ok(if s.is_empty() {
+        vec![]
+    } else if sep.is_empty() {
+        vec![s]
+    } else {
+        s.split(sep).map(String::from).collect()
+    })
+}
+
+/* runs code for each value with %_loop_count% and %_loop_value% set */
+fn each(
+    program: &Program,
+    env: &mut Environment,
+    values: Vec<String>,
+    code: &[Expr],
+) -> Result<Vec<String>, Error> {
+    let mut ret = vec![];
+    for (i, value) in values.into_iter().enumerate() {
+        set_values(env, "_loop_count", vec![(i + 1).to_string()]);
+        set_values(env, "_loop_value", vec![value]);
+        ret.push(program.evaluate_exprs(env, code)?.val);
+    }
+    Ok(ret)
+}
+
+/* %name%, multiple values are joined with "; " and missing fields are
+ * empty */
+pub(crate) fn variable(env: &Environment, name: &str) -> Value {
+    match env.metadata().get(name) {
+        Some(values) => text(values.join(JOINER)),
+        None => match env.get_variable(name) {
+            value @ Value { cond: true, .. } => text(value.val),
+            _ => text(String::new()),
+        },
+    }
+}
+
+/* parses a Picard script.  Unlike foobar2000 `'` and `[` are plain text,
+ * `\` escapes one of $%(),\ or makes \n and \t, and lines starting with #
+ * are comments.  Outside of functions ( ) and , are plain text too */
+pub(crate) fn parse(input: &str) -> Result<Vec<Expr>, Error> {
+    let script: String = input
+        .split_inclusive('\n')
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let (exprs, _) = parse_expression(&mut script.chars().peekable(), true)?;
+    Ok(exprs)
+}
+
+/* the expressions up to the end of the script or, in a function, up to the
+ * , or ) after the argument which is returned as well */
+fn parse_expression(chars: &mut Peekable<Chars>, top: bool) -> Result<(Vec<Expr>, char), Error> {
+    let mut exprs = vec![];
+    let mut literal = String::new();
+    let end = loop {
+        let Some(c) = chars.next() else {
+            if top {
+                break '\0';
+            }
+            return Err(ParseError);
+        };
+        match c {
+            ',' | ')' if !top => break c,
+            '(' if !top => return Err(ParseError),
+            '$' | '%' => {
+                if !literal.is_empty() {
+                    exprs.push(Expr::Literal(std::mem::take(&mut literal)));
+                }
+                exprs.push(if c == '$' {
+                    parse_function(chars)?
+                } else {
+                    parse_variable(chars)?
+                });
+            }
+            '\\' => literal.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(c @ ('$' | '%' | '(' | ')' | ',' | '\\')) => c,
+                _ => return Err(ParseError),
+            }),
+            c => literal.push(c),
+        }
+    };
+    if !literal.is_empty() {
+        exprs.push(Expr::Literal(literal));
+    }
+    Ok((exprs, end))
+}
+
+/* $name(args) after the $ */
+fn parse_function(chars: &mut Peekable<Chars>) -> Result<Expr, Error> {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    if name.is_empty() || chars.next() != Some('(') {
+        return Err(ParseError);
+    }
+    let mut args = vec![];
+    loop {
+        let (arg, end) = parse_expression(chars, false)?;
+        args.push(arg);
+        if end == ')' {
+            return Ok(Expr::FuncCall(name, args));
+        }
+    }
+}
+
+/* %name% after the first % */
+fn parse_variable(chars: &mut Peekable<Chars>) -> Result<Expr, Error> {
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some('%') => return Ok(Expr::Variable(name)),
+            Some(c) if c.is_alphanumeric() || c == '_' || c == ':' => name.push(c),
+            _ => return Err(ParseError),
+        }
+    }
+}
+
+/*
+ * $get(name)
+ * Returns the variable name, like %name%.
+ */
+fn get(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("get", args, 1, 1)?;
+    let name = arg(program, env, args, 0)?;
+    Ok(variable(env, &name))
+}
+
+/*
+ * $copy(new,old)
+ * Copies all the values of the variable old to new.
+ */
+fn copy(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("copy", args, 2, 2)?;
+    let new = arg(program, env, args, 0)?;
+    let old = arg(program, env, args, 1)?;
+    let values = env.metadata().get(&old).cloned().unwrap_or_default();
+    set_values(env, &new, values);
+    Ok(text(String::new()))
+}
+
+/*
+ * $copymerge(new,old[,keep_duplicates])
+ * Adds the values of old to those of new, without duplicates unless
+ * keep_duplicates is set.
+ */
+fn copymerge(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("copymerge", args, 2, 3)?;
+    let new = arg(program, env, args, 0)?;
+    let old = arg(program, env, args, 1)?;
+    let keep_duplicates = !arg(program, env, args, 2)?.is_empty();
+    let mut values = env.metadata().get(&new).cloned().unwrap_or_default();
+    values.extend(env.metadata().get(&old).cloned().unwrap_or_default());
+    if !keep_duplicates {
+        let mut unique: Vec<String> = vec![];
+        for value in values {
+            if !unique.contains(&value) {
+                unique.push(value);
+            }
+        }
+        values = unique;
+    }
+    set_values(env, &new, values);
+    Ok(text(String::new()))
+}
+
+/*
+ * $lenmulti(name[,separator])
+ * Returns the number of values of the multi-value name.
+ */
+fn lenmulti(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("lenmulti", args, 1, 2)?;
+    let sep = separator(program, env, args, 1)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    Ok(text(values.len().to_string()))
+}
+
+/*
+ * $getmulti(name,index[,separator])
+ * Returns the value at the 0-based index of the multi-value name, negative
+ * indices count from the end.
+ */
+fn getmulti(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("getmulti", args, 2, 3)?;
+    let sep = separator(program, env, args, 2)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    let len = values.len() as i64;
+    let index = match number(&arg(program, env, args, 1)?) {
+        Some(i) if i < 0 => i + len,
+        Some(i) => i,
+        None => return Ok(text(String::new())),
+    };
+    Ok(text(if index >= 0 && index < len {
+        values[index as usize].clone()
+    } else {
+        String::new()
+    }))
+}
+
+/*
+ * $foreach(name,code[,separator])
+ * Runs code for each value of the multi-value name with %_loop_count% and
+ * %_loop_value% set.  The output of code is dropped, it is run for what it
+ * sets.
+ */
+fn foreach(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("foreach", args, 2, 3)?;
+    let sep = separator(program, env, args, 2)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    each(program, env, values, &args[1])?;
+    Ok(text(String::new()))
+}
+
+/*
+ * $while(condition,code)
+ * Runs code as long as condition is true, up to 999 times, with
+ * %_loop_count% set.  Like $foreach the output of code is dropped.
+ */
+fn while_(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("while", args, 2, 2)?;
+    for i in 1..=MAX_LOOPS {
+        set_values(env, "_loop_count", vec![i.to_string()]);
+        if !program.evaluate_exprs(env, &args[0])?.cond {
+            break;
+        }
+        program.evaluate_exprs(env, &args[1])?;
+    }
+    Ok(text(String::new()))
+}
+
+/*
+ * $map(name,code[,separator])
+ * Replaces each value of the multi-value name with the output of code run
+ * with %_loop_count% and %_loop_value% set.
+ */
+fn map(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("map", args, 2, 3)?;
+    let sep = separator(program, env, args, 2)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    Ok(text(each(program, env, values, &args[1])?.join(&sep)))
+}
+
+/*
+ * $join(name,text[,separator])
+ * Joins the values of the multi-value name with text.
+ */
+fn join(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("join", args, 2, 3)?;
+    let sep = separator(program, env, args, 2)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    let joiner = arg(program, env, args, 1)?;
+    Ok(text(values.join(&joiner)))
+}
+
+/*
+ * $slice(name,start[,end[,separator]])
+ * Returns the values of the multi-value name from start up to end,
+ * negative positions count from the end.
+ */
+fn slice(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("slice", args, 2, 4)?;
+    let sep = separator(program, env, args, 3)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    let len = values.len() as i64;
+    let position = |s: String, default: i64| -> Option<usize> {
+        let i = if s.is_empty() { default } else { number(&s)? };
+        let i = if i < 0 { i + len } else { i };
+        Some(i.clamp(0, len) as usize)
+    };
+    let start = position(arg(program, env, args, 1)?, 0);
+    let end = position(arg(program, env, args, 2)?, len);
+    Ok(text(match (start, end) {
+        (Some(start), Some(end)) if start < end => values[start..end].join(&sep),
+        _ => String::new(),
+    }))
+}
+
+/*
+ * $unique(name[,case_sensitive[,separator]])
+ * Returns the distinct values of the multi-value name, sorted.  Values
+ * differing only in case are the same unless case_sensitive is set.
+ */
+fn unique(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("unique", args, 1, 3)?;
+    let sep = separator(program, env, args, 2)?;
+    let values = multi(program, env, &args[0], &sep)?;
+    let case_sensitive = !arg(program, env, args, 1)?.is_empty();
+    let key = |v: &str| {
+        if case_sensitive {
+            String::from(v)
+        } else {
+            v.to_lowercase()
+        }
+    };
+    let mut unique: Vec<String> = vec![];
+    for value in values {
+        match unique.iter().position(|u| key(u) == key(&value)) {
+            /* like Picard the last spelling wins */
+            Some(i) => unique[i] = value,
+            None => unique.push(value),
+        }
+    }
+    unique.sort();
+    Ok(text(unique.join(&sep)))
+}
+
+/*
+ * $reverse(name[,separator])
+ * Returns the values of the multi-value name in reverse order.
+ */
+fn reverse(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("reverse", args, 1, 2)?;
+    let sep = separator(program, env, args, 1)?;
+    let mut values = multi(program, env, &args[0], &sep)?;
+    values.reverse();
+    Ok(text(values.join(&sep)))
+}
+
+/*
+ * $if(condition,then[,else])
+ * Only the branch taken is evaluated.
+ */
+fn if_(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("if", args, 2, 3)?;
+    if program.evaluate_exprs(env, &args[0])?.cond {
+        program.evaluate_exprs(env, &args[1])
+    } else {
+        Ok(text(arg(program, env, args, 2)?))
+    }
+}
+
+/*
+ * $if2(a,b,...)
+ * Returns the first argument that is not empty, the later ones are not
+ * evaluated.
+ */
+fn if2(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    for exprs in args {
+        let value = program.evaluate_exprs(env, exprs)?;
+        if value.cond {
+            return Ok(value);
+        }
+    }
+    Ok(text(String::new()))
+}
+
+/*
+ * $and(a,b,...)
+ * Returns 1 if all the arguments are not empty, stopping at the first empty
+ * one.
+ */
+fn and(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    for exprs in args {
+        if !program.evaluate_exprs(env, exprs)?.cond {
+            return Ok(boolean(false));
+        }
+    }
+    Ok(boolean(true))
+}
+
+/*
+ * $or(a,b,...)
+ * Returns 1 if any argument is not empty, stopping at the first one.
+ */
+fn or(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    for exprs in args {
+        if program.evaluate_exprs(env, exprs)?.cond {
+            return Ok(boolean(true));
+        }
+    }
+    Ok(boolean(false))
+}
+
+/*
+ * $not(a)
+ * Returns 1 if a is empty.
+ */
+fn not(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    check("not", args, 1, 1)?;
+    Ok(boolean(arg(program, env, args, 0)?.is_empty()))
+}
+
+/* evaluates both arguments of a comparison */
+fn pair(
+    name: &str,
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+) -> Result<(String, String), Error> {
+    check(name, args, 2, 2)?;
+    Ok((arg(program, env, args, 0)?, arg(program, env, args, 1)?))
+}
+
+/* compares two integers, anything else is false */
+fn compare(
+    name: &str,
+    program: &Program,
+    env: &mut Environment,
+    args: &[Vec<Expr>],
+    f: fn(i64, i64) -> bool,
+) -> Result<Value, Error> {
+    let (a, b) = pair(name, program, env, args)?;
+    Ok(boolean(
+        matches!((number(&a), number(&b)), (Some(a), Some(b)) if f(a, b)),
+    ))
+}
+
+/*
+ * $eq(a,b)
+ * Returns 1 if a and b are the same text.
+ */
+fn eq(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (a, b) = pair("eq", program, env, args)?;
+    Ok(boolean(a == b))
+}
+
+/*
+ * $ne(a,b)
+ * Returns 1 if a and b are different texts.
+ */
+fn ne(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    let (a, b) = pair("ne", program, env, args)?;
+    Ok(boolean(a != b))
+}
+
+/* $lt(a,b), $lte(a,b), $gt(a,b) and $gte(a,b) compare integers */
+fn lt(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("lt", program, env, args, |a, b| a < b)
+}
+
+fn lte(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("lte", program, env, args, |a, b| a <= b)
+}
+
+fn gt(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("gt", program, env, args, |a, b| a > b)
+}
+
+fn gte(program: &Program, env: &mut Environment, args: &[Vec<Expr>]) -> Result<Value, Error> {
+    compare("gte", program, env, args, |a, b| a >= b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use std::collections::HashMap;
+
+    fn run(script: &str, tags: &[(&str, &[&str])]) -> String {
+        let mut program = Program::new();
+        program.set_dialect(Dialect::Picard);
+        program.parse(script).unwrap();
+        let metadata: HashMap<String, Vec<String>> = tags
+            .iter()
+            .map(|(k, v)| {
+                (
+                    String::from(*k),
+                    v.iter().map(|s| String::from(*s)).collect(),
+                )
+            })
+            .collect();
+        program.run_with_meta(metadata).unwrap()
+    }
+
+    const ARTISTS: &[(&str, &[&str])] = &[("artist", &["B", "a", "C", "A"])];
+
+    #[test]
+    fn test_functions_sorted() {
+        assert!(FUNCTIONS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(run("%artist%", ARTISTS), "B; a; C; A");
+        assert_eq!(run("%missing%x%missing%", &[]), "x");
+        assert_eq!(run("$set(_hidden,1)%_hidden%", &[]), "1");
+        assert_eq!(run("$set(a,1)$set(a,)%a%", &[]), "");
+        assert_eq!(run("$get(artist)", ARTISTS), "B; a; C; A");
+    }
+
+    #[test]
+    fn test_copy_unset() {
+        let tags: &[(&str, &[&str])] = &[("a", &["1", "2"]), ("b", &["2", "3"])];
+        assert_eq!(run("$copy(c,a)$lenmulti(%c%)", tags), "2");
+        assert_eq!(run("$copymerge(a,b)%a%", tags), "1; 2; 3");
+        assert_eq!(run("$copymerge(a,b,1)%a%", tags), "1; 2; 2; 3");
+        assert_eq!(run("$unset(a)%a%%b%", tags), "2; 3");
+        assert_eq!(run("$unset(*)%a%%b%", tags), "");
+    }
+
+    #[test]
+    fn test_multi() {
+        assert_eq!(run("$lenmulti(%artist%)", ARTISTS), "4");
+        assert_eq!(run("$lenmulti(x; y)", &[]), "2");
+        assert_eq!(run("$lenmulti(x-y-z,-)", &[]), "3");
+        assert_eq!(run("$lenmulti(%missing%)", &[]), "0");
+        assert_eq!(run("$getmulti(%artist%,1)", ARTISTS), "a");
+        assert_eq!(run("$getmulti(%artist%,-1)", ARTISTS), "A");
+        assert_eq!(run("$getmulti(%artist%,9)", ARTISTS), "");
+        assert_eq!(run("$join(%artist%,+)", ARTISTS), "B+a+C+A");
+        assert_eq!(run("$slice(%artist%,1,3)", ARTISTS), "a; C");
+        assert_eq!(run("$slice(%artist%,-2)", ARTISTS), "C; A");
+        assert_eq!(run("$slice(%artist%,3,1)", ARTISTS), "");
+        assert_eq!(run("$unique(%artist%)", ARTISTS), "A; B; C");
+        assert_eq!(run("$unique(%artist%,1)", ARTISTS), "A; B; C; a");
+        assert_eq!(run("$reverse(%artist%)", ARTISTS), "A; C; a; B");
+        assert_eq!(run("$reverse(1.2.3,.)", &[]), "3.2.1");
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_eq!(
+            run(
+                "$foreach(%artist%,$set(_a,%_a%%_loop_count%=%_loop_value% ))%_a%",
+                ARTISTS
+            ),
+            "1=B 2=a 3=C 4=A "
+        );
+        assert_eq!(run("$foreach(%artist%,x)", ARTISTS), "");
+        assert_eq!(
+            run("$map(%artist%,$lower(%_loop_value%))", ARTISTS),
+            "b; a; c; a"
+        );
+        assert_eq!(
+            run(
+                "$while($lt(%_loop_count%,4),$set(_n,%_n%%_loop_count%))%_n%",
+                &[]
+            ),
+            "123"
+        );
+        assert_eq!(run("$while(1,$set(_n,%_loop_count%))%_n%", &[]), "999");
+        assert_eq!(run("$while(1,x)", &[]), "");
+    }
+
+    #[test]
+    fn test_lexing() {
+        assert_eq!(run("Don't [Live]", &[]), "Don't [Live]");
+        assert_eq!(run("a (b, c)", &[]), "a (b, c)");
+        assert_eq!(run("\\$\\%\\(\\)\\,\\\\\\n\\t", &[]), "$%(),\\\n\t");
+        assert_eq!(run("$if(1,a\\,b\\))", &[]), "a,b)");
+        assert_eq!(run("# comment\n%a%\n #x\n#y", &[("a", &["1"])]), "1\n #x\n");
+        assert_eq!(
+            parse("$f(%a:b%,)x").unwrap(),
+            vec![
+                Expr::FuncCall(
+                    String::from("f"),
+                    vec![vec![Expr::Variable(String::from("a:b"))], vec![]]
+                ),
+                Expr::Literal(String::from("x"))
+            ]
+        );
+        for script in ["$f(a(b))", "$f(a", "\\q", "%a b%", "%a", "$ (x)", "$f x"] {
+            assert_eq!(parse(script), Err(ParseError), "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_control() {
+        assert_eq!(run("$if(%a%,yes,no)", &[("a", &["1"])]), "yes");
+        assert_eq!(run("$if(%a%,yes,no)", &[]), "no");
+        assert_eq!(run("$if(,yes,no)", &[]), "no");
+        /* only the branch taken runs */
+        assert_eq!(run("$if(1,,$set(x,1))%x%", &[]), "");
+        assert_eq!(run("$if2(%a%,,b,$set(x,1))%x%", &[]), "b");
+        assert_eq!(run("$and(1,x)$or(,)$not()", &[]), "11");
+        assert_eq!(run("$eq(a,a)$eq(a,A)$ne(a,b)", &[]), "11");
+        assert_eq!(run("$lt(2,10)$gt(2,10)$gte(2,2)$lte(x,2)", &[]), "11");
+        /* the foobar2000 functions are still there */
+        assert_eq!(run("$upper(%artist%)", ARTISTS), "B; A; C; A");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut program = Program::new();
+        program.set_dialect(Dialect::Picard);
+        program.parse("$set(a)").unwrap();
+        assert_eq!(
+            program.run(),
+            Err(InvalidNativeFunctionArgs(String::from("set"), 1))
+        );
+    }
+}
@@ -7,7 +7,7 @@ use crate::types::Error::*;
 fn split_first(s: String, args: Vec<String>) -> Option<(String, String)> {
     for pre in args {
         if s.starts_with(&pre) {
-            let splat = s.split_at(pre.bytes().len());
+            let splat = s.split_at(pre.len());
             return Some((String::from(splat.0), String::from(splat.1)));
         }
     }
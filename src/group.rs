@@ -0,0 +1,181 @@
+use crate::program::Program;
+use crate::track::Track;
+use crate::types::Error;
+
+use std::ops::Range;
+
+/// A run of consecutive tracks with the same group key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    /// The output of the group-key program, the same for all the tracks
+    pub key: String,
+    /// The indices of the tracks of the group
+    pub range: Range<usize>,
+    /// The rendered header, if a header program is set
+    pub header: Option<String>,
+    /// The rendered footer, if a footer program is set
+    pub footer: Option<String>,
+}
+
+/// Splits an ordered list of tracks into groups of consecutive tracks that
+/// have the same output for the group-key program, e.g.
+/// `%album artist% - %album%`.  Headers and footers are evaluated once per
+/// group with [`Program::run_group`] so they can use the fields of the first
+/// track of the group as well as the aggregate functions and fields.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::group::Grouping;
+/// # use std::collections::HashMap;
+/// let tracks: Vec<HashMap<String, Vec<String>>> = ["A", "A", "B"]
+///     .iter()
+///     .map(|album| HashMap::from([("album".to_string(), vec![album.to_string()])]))
+///     .collect();
+/// let mut grouping = Grouping::parse("%album%").unwrap();
+/// grouping.parse_header("%album% (%group_count%)").unwrap();
+/// let groups = grouping.group(&tracks).unwrap();
+/// assert_eq!(groups.len(), 2);
+/// assert_eq!(groups[0].range, 0..2);
+/// assert_eq!(groups[0].header.as_deref(), Some("A (2)"));
+/// assert_eq!(groups[1].header.as_deref(), Some("B (1)"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Grouping {
+    key: Program,
+    header: Option<Program>,
+    footer: Option<Program>,
+}
+
+fn parse_program(instr: &str) -> Result<Program, Error> {
+    let mut program = Program::new();
+    program.parse(instr)?;
+    Ok(program)
+}
+
+impl Grouping {
+    /// Constructs a new `Grouping` from an already parsed group-key program
+    pub fn new(key: Program) -> Self {
+        Grouping {
+            key,
+            header: None,
+            footer: None,
+        }
+    }
+
+    /// Parses `instr` into the group-key program of a new `Grouping`
+    pub fn parse(instr: &str) -> Result<Self, Error> {
+        Ok(Grouping::new(parse_program(instr)?))
+    }
+
+    /// Sets the program rendering the header of each group
+    pub fn set_header(&mut self, header: Program) {
+        self.header = Some(header);
+    }
+
+    /// Parses `instr` into the program rendering the header of each group
+    pub fn parse_header(&mut self, instr: &str) -> Result<(), Error> {
+        self.set_header(parse_program(instr)?);
+        Ok(())
+    }
+
+    /// Sets the program rendering the footer of each group
+    pub fn set_footer(&mut self, footer: Program) {
+        self.footer = Some(footer);
+    }
+
+    /// Parses `instr` into the program rendering the footer of each group
+    pub fn parse_footer(&mut self, instr: &str) -> Result<(), Error> {
+        self.set_footer(parse_program(instr)?);
+        Ok(())
+    }
+
+    /// Splits `tracks` into groups of consecutive tracks with the same key.
+    /// The tracks are not reordered, sort them first to get one group per
+    /// key.
+    pub fn group<T: Track>(&self, tracks: &[T]) -> Result<Vec<Group>, Error> {
+        let mut groups: Vec<Group> = vec![];
+        for (i, track) in tracks.iter().enumerate() {
+            let key = self.key.evaluate(track)?.val;
+            match groups.last_mut() {
+                Some(group) if group.key == key => group.range.end = i + 1,
+                _ => groups.push(Group {
+                    key,
+                    range: i..i + 1,
+                    header: None,
+                    footer: None,
+                }),
+            }
+        }
+
+        for group in groups.iter_mut() {
+            let members = &tracks[group.range.clone()];
+            if let Some(header) = &self.header {
+                group.header = Some(header.run_group(members)?);
+            }
+            if let Some(footer) = &self.footer {
+                group.footer = Some(footer.run_group(members)?);
+            }
+        }
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::TrackData;
+
+    fn track(album: &str, length: &str) -> TrackData {
+        let mut track = TrackData::default();
+        track
+            .metadata
+            .insert(String::from("album"), vec![String::from(album)]);
+        track
+            .info
+            .insert(String::from("length_seconds"), String::from(length));
+        track
+    }
+
+    #[test]
+    fn test_group_consecutive() {
+        let tracks = vec![track("A", "1"), track("B", "1"), track("A", "1")];
+        let groups = Grouping::parse("%album%").unwrap().group(&tracks).unwrap();
+        let keys: Vec<(&str, Range<usize>)> = groups
+            .iter()
+            .map(|g| (g.key.as_str(), g.range.clone()))
+            .collect();
+        assert_eq!(keys, vec![("A", 0..1), ("B", 1..2), ("A", 2..3)]);
+        assert_eq!(groups[0].header, None);
+        assert_eq!(groups[0].footer, None);
+    }
+
+    #[test]
+    fn test_header_footer() {
+        let tracks = vec![track("A", "60"), track("A", "90"), track("B", "30")];
+        let mut grouping = Grouping::parse("%album%").unwrap();
+        grouping.parse_header("%album%").unwrap();
+        grouping
+            .parse_footer("%group_count% tracks, %group_length%")
+            .unwrap();
+        let groups = grouping.group(&tracks).unwrap();
+        assert_eq!(groups[0].header.as_deref(), Some("A"));
+        assert_eq!(groups[0].footer.as_deref(), Some("2 tracks, 2:30"));
+        assert_eq!(groups[1].footer.as_deref(), Some("1 tracks, 0:30"));
+    }
+
+    #[test]
+    fn test_empty() {
+        let tracks: Vec<TrackData> = vec![];
+        let grouping = Grouping::parse("%album%").unwrap();
+        assert_eq!(grouping.group(&tracks).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_error() {
+        let tracks = vec![track("A", "1")];
+        let mut grouping = Grouping::parse("%album%").unwrap();
+        grouping.parse_header("$add()").unwrap();
+        assert!(grouping.group(&tracks).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::parser;
+use crate::types::Error;
+use crate::types::Expr;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/* a pattern flattened into steps, an optional section is entered or
+ * jumped over to the step after its end */
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Literal(String),
+    Capture(String),
+    Optional(usize),
+}
+
+/// A titleformat pattern run in reverse to guess the tags of a file from
+/// its name, e.g. `%artist% - %album%/%tracknumber%. %title%`.
+///
+/// Only literals, `%field%` captures and `[...]` optional sections are
+/// allowed.  A capture takes at least one character and as few as possible,
+/// it never spans a directory separator, and optional sections are tried
+/// before being skipped.  A field captured more than once gets every
+/// distinct value.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::guess::Matcher;
+/// # use titleformat_rs::program::Program;
+/// let matcher = Matcher::parse("%artist% - %album%/[%tracknumber%. ]%title%").unwrap();
+/// let metadata = matcher
+///     .apply_path("/music/Lorde - Melodrama/01. Green Light.flac")
+///     .unwrap();
+/// assert_eq!(metadata["album"], vec!["Melodrama"]);
+/// assert_eq!(metadata["tracknumber"], vec!["01"]);
+///
+/// let mut program = Program::new();
+/// program.parse("%title% by %artist%").unwrap();
+/// assert_eq!(program.run_with_meta(metadata).unwrap(), "Green Light by Lorde");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Matcher {
+    steps: Vec<Step>,
+    /* lowercase, without the dot */
+    extensions: Vec<String>,
+}
+
+/* the extensions apply_path strips unless told otherwise, a name like
+ * "01. Intro" has no extension */
+const AUDIO_EXTENSIONS: [&str; 24] = [
+    "aac", "ac3", "aif", "aiff", "alac", "ape", "dff", "dsf", "dts", "flac", "m4a", "m4b", "mka",
+    "mp2", "mp3", "mp4", "mpc", "oga", "ogg", "opus", "tak", "tta", "wav", "wma",
+];
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+fn compile(exprs: &[Expr], steps: &mut Vec<Step>) -> Result<(), Error> {
+    for expr in exprs {
+        match expr {
+            Expr::Literal(s) => match steps.last_mut() {
+                Some(Step::Literal(last)) => last.push_str(s),
+                _ => steps.push(Step::Literal(s.clone())),
+            },
+            Expr::Variable(name) => steps.push(Step::Capture(name.clone())),
+            Expr::Conditional(inner) => {
+                let start = steps.len();
+                steps.push(Step::Optional(0));
+                compile(inner, steps)?;
+                steps[start] = Step::Optional(steps.len());
+            }
+            Expr::MultiVariable(name) => {
+                return Err(Error::PatternError(format!(
+                    "%<{}>% cannot be captured",
+                    name
+                )))
+            }
+            Expr::FuncCall(name, _) => {
+                return Err(Error::PatternError(format!("${} cannot be matched", name)))
+            }
+            Expr::ExprValue(_) => {
+                return Err(Error::PatternError(String::from("unexpected value")))
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Matcher {
+    /// Parses `pattern` into a new `Matcher`
+    pub fn parse(pattern: &str) -> Result<Self, Error> {
+        let mut steps = vec![];
+        compile(&parser::parse(pattern)?, &mut steps)?;
+        Ok(Matcher {
+            steps,
+            extensions: AUDIO_EXTENSIONS.iter().map(|e| String::from(*e)).collect(),
+        })
+    }
+
+    /// Sets the extensions [`Matcher::apply_path`] removes from the file
+    /// name before matching, without the dot and ignoring case.  By default
+    /// these are the extensions of the common audio formats.
+    pub fn set_extensions(&mut self, extensions: &[&str]) {
+        self.extensions = extensions.iter().map(|e| e.to_lowercase()).collect();
+    }
+
+    /// Matches the whole of `s`, returning the captured fields or `None` if
+    /// it does not match
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::guess::Matcher;
+    /// let matcher = Matcher::parse("%artist% - %title%").unwrap();
+    /// let metadata = matcher.apply("Daft Punk - One More Time - Radio Edit").unwrap();
+    /// assert_eq!(metadata["artist"], vec!["Daft Punk"]);
+    /// assert_eq!(metadata["title"], vec!["One More Time - Radio Edit"]);
+    /// assert!(matcher.apply("Untitled").is_none());
+    /// ```
+    pub fn apply(&self, s: &str) -> Option<HashMap<String, Vec<String>>> {
+        let mut captures = vec![];
+        if !self.step(s, 0, 0, &mut captures, &mut HashSet::new()) {
+            return None;
+        }
+        let mut ret: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, value) in captures {
+            let values = ret.entry(String::from(name)).or_default();
+            if !values.iter().any(|v| v == value) {
+                values.push(String::from(value));
+            }
+        }
+        Some(ret)
+    }
+
+    /// Matches the end of `path` without the extension of the file, taking
+    /// as many of its directories as the pattern can.  Only the extensions
+    /// set with [`Matcher::set_extensions`] are removed.
+    pub fn apply_path<P: AsRef<Path>>(&self, path: P) -> Option<HashMap<String, Vec<String>>> {
+        let path = path.as_ref();
+        let known = match path.extension() {
+            Some(ext) => self
+                .extensions
+                .contains(&ext.to_string_lossy().to_lowercase()),
+            None => false,
+        };
+        let path = if known {
+            path.with_extension("")
+        } else {
+            path.to_path_buf()
+        };
+        let s = path.to_string_lossy();
+        let starts =
+            std::iter::once(0).chain(s.match_indices(is_separator).map(|(i, c)| i + c.len()));
+        starts
+            .filter(|&i| i < s.len())
+            .find_map(|i| self.apply(&s[i..]))
+    }
+
+    /* backtracks through the steps from step i at byte pos of s.  Whether
+     * the rest matches does not depend on what was captured before, so the
+     * steps and positions that failed are remembered in failed to keep
+     * patterns with many captures from taking exponential time */
+    fn step<'a>(
+        &'a self,
+        s: &'a str,
+        i: usize,
+        pos: usize,
+        captures: &mut Vec<(&'a str, &'a str)>,
+        failed: &mut HashSet<(usize, usize)>,
+    ) -> bool {
+        if failed.contains(&(i, pos)) {
+            return false;
+        }
+        let matched = match self.steps.get(i) {
+            None => pos == s.len(),
+            Some(Step::Literal(lit)) => {
+                s[pos..].starts_with(lit.as_str())
+                    && self.step(s, i + 1, pos + lit.len(), captures, failed)
+            }
+            Some(Step::Optional(end)) => {
+                self.step(s, i + 1, pos, captures, failed)
+                    || self.step(s, *end, pos, captures, failed)
+            }
+            Some(Step::Capture(name)) => {
+                let len = captures.len();
+                let mut matched = false;
+                for (offset, c) in s[pos..].char_indices() {
+                    if is_separator(c) {
+                        break;
+                    }
+                    let end = pos + offset + c.len_utf8();
+                    captures.push((name.as_str(), &s[pos..end]));
+                    if self.step(s, i + 1, end, captures, failed) {
+                        matched = true;
+                        break;
+                    }
+                    captures.truncate(len);
+                }
+                matched
+            }
+        };
+        if !matched {
+            failed.insert((i, pos));
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guess(pattern: &str, s: &str) -> Option<Vec<(String, Vec<String>)>> {
+        let metadata = Matcher::parse(pattern).unwrap().apply(s)?;
+        let mut ret: Vec<(String, Vec<String>)> = metadata.into_iter().collect();
+        ret.sort();
+        Some(ret)
+    }
+
+    fn fields(fields: &[(&str, &[&str])]) -> Option<Vec<(String, Vec<String>)>> {
+        Some(
+            fields
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        String::from(*k),
+                        v.iter().map(|s| String::from(*s)).collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(guess("abc", "abc"), fields(&[]));
+        assert_eq!(guess("abc", "abcd"), None);
+        assert_eq!(guess("", ""), fields(&[]));
+        assert_eq!(guess("'%'%a%", "%x"), fields(&[("a", &["x"])]));
+    }
+
+    #[test]
+    fn test_captures() {
+        assert_eq!(
+            guess("%a%-%b%", "x-y-z"),
+            fields(&[("a", &["x"]), ("b", &["y-z"])])
+        );
+        assert_eq!(guess("%a%-%b%", "-y"), None);
+        assert_eq!(guess("%a%/%b%", "x/y/z"), None);
+        assert_eq!(
+            guess("%a% (%b%)", "ça (va)"),
+            fields(&[("a", &["ça"]), ("b", &["va"])])
+        );
+    }
+
+    #[test]
+    fn test_optional() {
+        let pattern = "[%tracknumber%. ]%title%[ (%version%)]";
+        assert_eq!(
+            guess(pattern, "3. Song (Live)"),
+            fields(&[
+                ("title", &["Song"]),
+                ("tracknumber", &["3"]),
+                ("version", &["Live"])
+            ])
+        );
+        assert_eq!(guess(pattern, "Song"), fields(&[("title", &["Song"])]));
+        assert_eq!(
+            guess("[[%a%-]%b%:]%c%", "y:z"),
+            fields(&[("b", &["y"]), ("c", &["z"])])
+        );
+    }
+
+    #[test]
+    fn test_repeated() {
+        assert_eq!(
+            guess("%artist%/%artist% - %title%", "A/A - T"),
+            fields(&[("artist", &["A"]), ("title", &["T"])])
+        );
+        assert_eq!(
+            guess("%artist% & %artist%", "A & B"),
+            fields(&[("artist", &["A", "B"])])
+        );
+    }
+
+    #[test]
+    fn test_many_captures() {
+        let pattern: String = (0..16).map(|i| format!("%f{}%", i)).collect();
+        let matcher = Matcher::parse(&format!("{}!", pattern)).unwrap();
+        assert!(matcher.apply(&"a".repeat(64)).is_none());
+        assert_eq!(
+            matcher
+                .apply(&format!("{}!", "a".repeat(64)))
+                .unwrap()
+                .len(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        let matcher = Matcher::parse("[%album%/]%title%").unwrap();
+        let metadata = matcher.apply_path("/music/Album/Title.mp3").unwrap();
+        assert_eq!(metadata["album"], vec!["Album"]);
+        assert_eq!(metadata["title"], vec!["Title"]);
+        let metadata = matcher.apply_path("Title.mp3").unwrap();
+        assert_eq!(metadata.get("album"), None);
+        assert!(Matcher::parse("%a% - %b%")
+            .unwrap()
+            .apply_path("/x/y.mp3")
+            .is_none());
+
+        let mut matcher = Matcher::parse("%tracknumber%. %title%").unwrap();
+        let metadata = matcher.apply_path("/music/01. Intro").unwrap();
+        assert_eq!(metadata["title"], vec!["Intro"]);
+        let metadata = matcher.apply_path("/music/01. Intro.FLAC").unwrap();
+        assert_eq!(metadata["title"], vec!["Intro"]);
+        matcher.set_extensions(&["XYZ"]);
+        let metadata = matcher.apply_path("/music/01. Intro.xyz").unwrap();
+        assert_eq!(metadata["title"], vec!["Intro"]);
+        let metadata = matcher.apply_path("/music/01. Intro.flac").unwrap();
+        assert_eq!(metadata["title"], vec!["Intro.flac"]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            Matcher::parse("$upper(%a%)"),
+            Err(Error::PatternError(String::from(
+                "$upper cannot be matched"
+            )))
+        );
+        assert!(Matcher::parse("%<artist>%").is_err());
+    }
+}
@@ -1,5 +1,19 @@
+pub mod autoplaylist;
+pub mod dialect;
+pub mod edit;
 pub mod environment;
 mod functions;
+pub mod group;
+pub mod guess;
 mod parser;
+pub mod path;
+pub mod playback;
+pub mod playlist;
 pub mod program;
+pub mod query;
+pub mod remap;
+pub mod rename;
+pub mod sort;
+pub mod track;
+pub mod tree;
 pub mod types;
@@ -3,7 +3,7 @@ use nom::bytes::complete::{tag, take_till, take_till1, take_until};
 use nom::character::complete::newline;
 use nom::combinator::{all_consuming, map, opt};
 use nom::multi::{fold_many0, fold_many1, many0, separated_list0};
-use nom::sequence::{delimited, preceded};
+use nom::sequence::{delimited, preceded, terminated};
 use nom::{IResult, Input, Parser};
 
 use crate::types::Error;
@@ -32,6 +32,11 @@ fn newlines(input: &str) -> IResult<&str, &str> {
 /* %varname%
  *
  * A field reference is a field name enclosed in percent signs, for example %artist%.
+ *
+ * %<varname>%
+ *
+ * Refers to each of the values of a multi-value field in turn, for example
+ * %<artist>%.
  */
 fn variable(input: &str) -> IResult<&str, Expr> {
     delimited(tag("%"), take_until("%"), tag("%"))
@@ -63,8 +68,12 @@ fn func(input: &str) -> IResult<&str, Expr> {
 
 fn find_func_arg_end(input: &str) -> IResult<&str, &str> {
     let mut stack = 1;
+    /* quoted text may contain anything, e.g. the ( and ) of a regexp */
+    let mut quoted = false;
     for (index, c) in input.iter_indices() {
         match c {
+            '\'' => quoted = !quoted,
+            _ if quoted => (),
             '$' => stack += 1,
             ')' => {
                 stack -= 1;
@@ -72,10 +81,8 @@ fn find_func_arg_end(input: &str) -> IResult<&str, &str> {
                     return Ok((input.take_from(index), input.take(index)));
                 }
             }
-            ',' => {
-                if stack == 1 {
-                    return Ok((input.take_from(index), input.take(index)));
-                }
+            ',' if stack == 1 => {
+                return Ok((input.take_from(index), input.take(index)));
             }
             _ => (),
         }
@@ -108,8 +115,11 @@ fn func_args(input: &str) -> IResult<&str, Vec<Vec<Expr>>> {
 */
 fn find_conditional_end(input: &str) -> IResult<&str, &str> {
     let mut stack = 1;
+    let mut quoted = false;
     for (index, c) in input.iter_indices() {
         match c {
+            '\'' => quoted = !quoted,
+            _ if quoted => (),
             '[' => stack += 1,
             ']' => {
                 stack -= 1;
@@ -128,7 +138,7 @@ fn find_conditional_end(input: &str) -> IResult<&str, &str> {
 fn conditional(input: &str) -> IResult<&str, Expr> {
     let (del_input, cond_expr) =
         delimited(tag("["), find_conditional_end, tag("]")).parse(input)?;
-    let (cond_input, expr) = conditional_expr(cond_expr).map(|(_, expr)| (del_input, expr))?;
+    let (cond_input, expr) = conditional_expr(cond_expr)?;
     if !cond_input.is_empty() {
         return Err(nom::Err::Error(nom::error::Error {
             input,
@@ -268,17 +278,23 @@ fn nested_expr(input: &str) -> IResult<&str, Expr> {
     alt((conditional, func, variable, standard_literal_expr)).parse(input)
 }
 fn expr(input: &str) -> IResult<&str, Vec<Expr>> {
-    fold_many0(nested_expr, Vec::new, move |mut acc, item| {
-        acc.push(item);
-        acc
-    })
+    terminated(
+        fold_many0(nested_expr, Vec::new, move |mut acc, item| {
+            acc.push(item);
+            acc
+        }),
+        /* a comment with nothing after it */
+        many0(comment),
+    )
     .parse(input)
 }
 
+/* text that cannot be parsed, such as an unterminated quote, is an error
+ * rather than the end of the program */
 pub fn parse(input: &str) -> Result<Vec<Expr>, Error> {
     match expr(input) {
-        Ok((_, expr)) => Ok(expr),
-        _e => Err(ParseError),
+        Ok(("", expr)) => Ok(expr),
+        _ => Err(ParseError),
     }
 }
 
@@ -291,7 +307,13 @@ fn parse_literal(literal: &str) -> Expr {
 }
 
 fn parse_varname(name: &str) -> Expr {
-    Variable(String::from(name))
+    match name
+        .strip_prefix('<')
+        .and_then(|name| name.strip_suffix('>'))
+    {
+        Some(name) => MultiVariable(String::from(name)),
+        None => Variable(String::from(name)),
+    }
 }
 
 fn parse_funccall(name: &str, args: Vec<Vec<Expr>>) -> Expr {
@@ -314,6 +336,14 @@ mod tests {
         assert_eq!(parsed, vec![Variable(String::from("ab"))]);
     }
 
+    #[test]
+    fn test_multi_variable() {
+        let parsed = parse("%<ab>%").unwrap();
+        assert_eq!(parsed, vec![MultiVariable(String::from("ab"))]);
+        let parsed = parse("%<ab%").unwrap();
+        assert_eq!(parsed, vec![Variable(String::from("<ab"))]);
+    }
+
     #[test]
     fn test_empty_funccall() {
         let parsed = parse("$ab()").unwrap();
@@ -386,6 +416,24 @@ mod tests {
         assert_eq!(parse("\'\'").unwrap(), vec![Literal(String::from("\'"))]);
     }
 
+    #[test]
+    fn test_escaped_function_args() {
+        assert_eq!(
+            parse("$f('a,b)$',c)").unwrap(),
+            vec![FuncCall(
+                String::from("f"),
+                vec![
+                    vec![Literal(String::from("a,b)$"))],
+                    vec![Literal(String::from("c"))]
+                ]
+            )]
+        );
+        assert_eq!(
+            parse("['a]b']").unwrap(),
+            vec![Conditional(vec![Literal(String::from("a]b"))])]
+        );
+    }
+
     #[test]
     fn test_function_newlines() {
         /* all newlines between tokens are ignored */
@@ -428,6 +476,13 @@ mod tests {
         assert_eq!(parse("$f(var"), Err(ParseError),);
     }
 
+    #[test]
+    fn test_leftover_input() {
+        assert_eq!(parse("Don't"), Err(ParseError));
+        assert_eq!(parse("a$f"), Err(ParseError));
+        assert_eq!(parse("a%b"), Err(ParseError));
+    }
+
     #[test]
     fn test_empty_comment() {
         assert_eq!(parse("//\n").unwrap(), vec![]);
@@ -511,6 +566,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_conditional_followed_by_literal() {
+        let parsed = parse("[%a%]b").unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                Conditional(vec![Variable(String::from("a"))]),
+                Literal(String::from("b")),
+            ]
+        );
+    }
+
     #[test]
     fn test_conditional_function() {
         let parsed = parse("[$a(b)]").unwrap();
@@ -0,0 +1,374 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How the values of fields and functions are made safe to use in file and
+/// folder names.  Literal `/` and `\` in a program separate directories,
+/// any of them, or any other character the file system does not allow,
+/// coming from a tag or function result is replaced.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::path::PathMode;
+/// # use titleformat_rs::program::Program;
+/// # use std::collections::HashMap;
+/// let mut program = Program::new();
+/// program.parse("%artist%/%title%").unwrap();
+/// let mut metadata = HashMap::new();
+/// metadata.insert("artist".into(), vec!["AC/DC".into()]);
+/// metadata.insert("title".into(), vec!["What: Why?".into()]);
+/// assert_eq!(
+///     program.run_path(&metadata, &PathMode::windows()).unwrap(),
+///     "AC_DC/What_ Why_"
+/// );
+/// assert_eq!(
+///     program.run_path(&metadata, &PathMode::linux()).unwrap(),
+///     "AC_DC/What: Why?"
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathMode {
+    invalid: Vec<char>,
+    replacement: String,
+    /* in bytes */
+    component_limit: Option<usize>,
+    /* in characters */
+    path_limit: Option<usize>,
+    /* no reserved device names, trailing dots or trailing spaces */
+    windows_names: bool,
+}
+
+/* CON, NUL, COM1, ... are devices on Windows, whatever the extension */
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/* splits "name.ext" into "name" and ".ext", an extension is short and only
+ * letters and digits */
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i)
+            if i > 0
+                && name.len() - i <= 9
+                && name[i + 1..].chars().all(|c| c.is_alphanumeric()) =>
+        {
+            name.split_at(i)
+        }
+        _ => (name, ""),
+    }
+}
+
+/* the longest prefix of s made of whole graphemes for which fits is true */
+fn truncate<F: Fn(&str) -> bool>(s: &str, fits: F) -> &str {
+    let mut end = 0;
+    for (i, g) in s.grapheme_indices(true) {
+        if !fits(&s[..i + g.len()]) {
+            break;
+        }
+        end = i + g.len();
+    }
+    &s[..end]
+}
+
+impl PathMode {
+    /// Constructs a new `PathMode` replacing the `invalid` characters, the
+    /// directory separators and the control characters with `replacement`
+    pub fn new(invalid: &[char], replacement: &str) -> Self {
+        let mut all = vec!['/', '\\'];
+        for c in invalid {
+            if !all.contains(c) {
+                all.push(*c);
+            }
+        }
+        PathMode {
+            invalid: all,
+            replacement: String::from(replacement),
+            component_limit: Some(255),
+            path_limit: None,
+            windows_names: false,
+        }
+    }
+
+    /// The characters Windows and FAT file systems do not allow:
+    /// `\ / : * ? " < > |`, reserved device names such as `CON` or `NUL`
+    /// and names ending in a dot or space are also avoided.
+    pub fn windows() -> Self {
+        let mut mode = Self::new(&[':', '*', '?', '"', '<', '>', '|'], "_");
+        mode.windows_names = true;
+        mode
+    }
+
+    /// The characters not allowed on macOS, where `:` is shown as `/`
+    pub fn macos() -> Self {
+        Self::new(&[':'], "_")
+    }
+
+    /// Only the directory separators, anything else is allowed on Linux
+    pub fn linux() -> Self {
+        Self::new(&[], "_")
+    }
+
+    /// The mode for the platform the program is running on
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Self::windows()
+        } else if cfg!(target_os = "macos") {
+            Self::macos()
+        } else {
+            Self::linux()
+        }
+    }
+
+    /// Sets the text the invalid characters are replaced with
+    pub fn set_replacement(&mut self, replacement: &str) {
+        self.replacement = String::from(replacement);
+    }
+
+    /// Sets the maximum length in bytes of each directory and file name, 255
+    /// by default.  Longer names are cut between graphemes, keeping the
+    /// extension of the file name, and do not end in a dot or space once
+    /// cut.
+    pub fn set_component_limit(&mut self, limit: Option<usize>) {
+        self.component_limit = limit;
+    }
+
+    /// Sets the maximum length in characters of the whole path, unlimited by
+    /// default.  The file name is shortened first, then the directories from
+    /// the deepest one.
+    pub fn set_path_limit(&mut self, limit: Option<usize>) {
+        self.path_limit = limit;
+    }
+
+    /// Sets whether names that Windows cannot use are avoided: the reserved
+    /// device names (`CON`, `NUL`, `COM1`, ...), also before a dot as in
+    /// `CON.txt`, get the replacement appended and trailing dots and spaces
+    /// are removed.  Set by
+    /// [`PathMode::windows`].
+    pub fn set_windows_names(&mut self, windows_names: bool) {
+        self.windows_names = windows_names;
+    }
+
+    /* whether Windows takes name for a device, it only looks at the part
+     * before the first dot */
+    fn is_reserved(&self, name: &str) -> bool {
+        let base = name.split('.').next().unwrap_or("");
+        self.windows_names && RESERVED.iter().any(|r| r.eq_ignore_ascii_case(base))
+    }
+
+    /* appends the replacement to a reserved device name: CON.x → CON_.x */
+    fn avoid_reserved(&self, name: String) -> String {
+        if !self.is_reserved(&name) {
+            return name;
+        }
+        let base = name.split('.').next().unwrap_or("").len();
+        format!("{}{}{}", &name[..base], self.replacement, &name[base..])
+    }
+
+    /* cuts stem so that stem + suffix + ext fits, never leaving a trailing
+     * dot or space nor an empty stem */
+    fn shorten(&self, stem: &str, suffix: &str, ext: &str, fits: &dyn Fn(&str) -> bool) -> String {
+        let shorter = truncate(stem, |s| fits(&format!("{}{}{}", s, suffix, ext)))
+            .trim_end_matches(['.', ' ']);
+        /* keep at least one grapheme, the limit of the whole path is then
+         * met by shortening the directories */
+        if shorter.is_empty() {
+            stem.graphemes(true).next().unwrap_or("").to_string()
+        } else {
+            shorter.to_string()
+        }
+    }
+
+    /* fixes one directory or file name to fit in limit bytes */
+    fn component(&self, name: &str, is_file: bool, fits: &dyn Fn(&str) -> bool) -> String {
+        /* . and .. would name the directory itself or its parent */
+        let dots;
+        let name = if name == "." || name == ".." {
+            dots = self.replacement.repeat(name.len());
+            &dots
+        } else {
+            name
+        };
+        let (stem, ext) = if is_file {
+            split_extension(name)
+        } else {
+            (name, "")
+        };
+        let stem = if self.windows_names {
+            stem.trim_end_matches(['.', ' '])
+        } else {
+            stem
+        };
+        let mut stem = self.avoid_reserved(stem.to_string());
+        if !fits(&format!("{}{}", stem, ext)) {
+            stem = self.shorten(&stem, "", ext, fits);
+            if self.is_reserved(&stem) {
+                /* cutting made a device name, cut more to make room for the
+                 * replacement */
+                let shorter = self.shorten(&stem, &self.replacement, ext, fits);
+                stem = self.avoid_reserved(shorter);
+            }
+        }
+        if stem.is_empty() && !name.is_empty() {
+            stem = self.replacement.clone();
+        }
+        stem + ext
+    }
+
+    /// Makes every directory and file name of `path` valid and enforces the
+    /// length limits.  Both `/` and `\` separate directories, the result
+    /// always uses `/` and is relative: leading separators are dropped and
+    /// empty directory names such as the one in `a//b` are left out, as
+    /// happens when a field is missing.  This is applied to the output of
+    /// [`Program::run_path`](crate::program::Program::run_path).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::path::PathMode;
+    /// let mut mode = PathMode::windows();
+    /// mode.set_component_limit(Some(8));
+    /// assert_eq!(mode.finish("con\\The Album.../01 Long title.flac"), "con_/The Albu/01.flac");
+    /// assert_eq!(mode.finish("/Album//01.flac"), "Album/01.flac");
+    /// ```
+    pub fn finish(&self, path: &str) -> String {
+        self.finish_under(path, 0)
+    }
+
+    /* finishes a path that will be put under a directory taking up prefix
+     * characters of the path limit */
+    pub(crate) fn finish_under(&self, path: &str, prefix: usize) -> String {
+        /* a leading separator would make the path absolute */
+        let names: Vec<&str> = path.split(is_separator).filter(|s| !s.is_empty()).collect();
+        let count = names.len();
+        let component_fits = |s: &str| !matches!(self.component_limit, Some(l) if s.len() > l);
+        let mut components: Vec<String> = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| self.component(name, i + 1 == count, &component_fits))
+            .collect();
+
+        if let Some(limit) = self.path_limit {
+            let mut total = prefix + components.join("/").chars().count();
+            for i in (0..count).rev() {
+                if total <= limit {
+                    break;
+                }
+                let len = components[i].chars().count();
+                let wanted = len.saturating_sub(total - limit);
+                let fits = |s: &str| component_fits(s) && s.chars().count() <= wanted;
+                let shorter = self.component(&components[i], i + 1 == count, &fits);
+                total = total - len + shorter.chars().count();
+                components[i] = shorter;
+            }
+        }
+        components.join("/")
+    }
+
+    /// Replaces the characters of `s` that cannot be part of a file name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::path::PathMode;
+    /// let mut mode = PathMode::windows();
+    /// mode.set_replacement("");
+    /// assert_eq!(mode.sanitize("a/b: c?"), "ab c");
+    /// ```
+    pub fn sanitize(&self, s: &str) -> String {
+        let mut ret = String::with_capacity(s.len());
+        for c in s.chars() {
+            if c.is_control() || self.invalid.contains(&c) {
+                ret.push_str(&self.replacement);
+            } else {
+                ret.push(c);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            PathMode::windows().sanitize("a\\b/c:d*e?f\"g<h>i|j\0k"),
+            "a_b_c_d_e_f_g_h_i_j_k"
+        );
+        assert_eq!(PathMode::macos().sanitize("a/b:c?"), "a_b_c?");
+        assert_eq!(PathMode::linux().sanitize("a\\b:c\n"), "a_b:c_");
+        assert_eq!(PathMode::new(&['x', '/'], "-").sanitize("x/y"), "--y");
+    }
+
+    #[test]
+    fn test_split_extension() {
+        assert_eq!(split_extension("a.flac"), ("a", ".flac"));
+        assert_eq!(split_extension("a.b.mp3"), ("a.b", ".mp3"));
+        assert_eq!(split_extension(".hidden"), (".hidden", ""));
+        assert_eq!(split_extension("Mr. Jones"), ("Mr. Jones", ""));
+        assert_eq!(split_extension("a"), ("a", ""));
+    }
+
+    #[test]
+    fn test_separators() {
+        let mode = PathMode::linux();
+        assert_eq!(mode.finish("/a//b\\c/"), "a/b/c");
+        assert_eq!(mode.finish("\\\\server/x"), "server/x");
+        assert_eq!(mode.finish("//"), "");
+    }
+
+    #[test]
+    fn test_windows_names() {
+        let mode = PathMode::windows();
+        assert_eq!(mode.finish("NUL/Com1.mp3"), "NUL_/Com1_.mp3");
+        assert_eq!(mode.finish("a.. /b . .flac"), "a/b.flac");
+        assert_eq!(mode.finish(".../x"), "_/x");
+        assert_eq!(mode.finish("CONSOLE/a"), "CONSOLE/a");
+        assert_eq!(
+            mode.finish("CON.x/con.backup.mp3"),
+            "CON_.x/con_.backup.mp3"
+        );
+        let mut mode = PathMode::windows();
+        mode.set_component_limit(Some(3));
+        assert_eq!(mode.finish("Concerts/x.flac"), "Co/x.flac");
+        assert_eq!(mode.finish("Nulls/x"), "Nu/x");
+        let mode = PathMode::linux();
+        assert_eq!(mode.finish("NUL/a. "), "NUL/a. ");
+        assert_eq!(mode.finish("./../x/.."), "_/__/x/__");
+    }
+
+    #[test]
+    fn test_component_limit() {
+        let mut mode = PathMode::linux();
+        assert_eq!(mode.finish(&"a".repeat(300)), "a".repeat(255));
+        mode.set_component_limit(Some(5));
+        /* é is two bytes, e + combining acute is three */
+        assert_eq!(mode.finish("ééé/e\u{301}e\u{301}"), "éé/e\u{301}");
+        assert_eq!(mode.finish("abcdefgh.ogg"), "a.ogg");
+        mode.set_component_limit(Some(4));
+        assert_eq!(mode.finish("abc def/x"), "abc/x");
+        assert_eq!(mode.finish("a...b/x"), "a/x");
+        mode.set_component_limit(None);
+        assert_eq!(mode.finish(&"a".repeat(300)), "a".repeat(300));
+    }
+
+    #[test]
+    fn test_path_limit() {
+        let mut mode = PathMode::windows();
+        mode.set_path_limit(Some(16));
+        assert_eq!(mode.finish("artist/album/title.mp3"), "artist/alb/t.mp3");
+        assert_eq!(
+            mode.finish("artist\\album/long title.mp3"),
+            "artist/alb/l.mp3"
+        );
+        assert_eq!(mode.finish("abc/d.mp3"), "abc/d.mp3");
+        mode.set_path_limit(Some(12));
+        assert_eq!(mode.finish("a . . . . . x/y.mp3"), "a/y.mp3");
+    }
+}
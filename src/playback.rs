@@ -0,0 +1,164 @@
+use crate::environment::value_string;
+use crate::environment::{Environment, Value};
+use crate::functions::derived::{format_time, length_seconds};
+
+/// The state of the player, for formatting now-playing lines
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::playback::PlaybackState;
+/// # use titleformat_rs::program::Program;
+/// # use std::collections::HashMap;
+/// let state = PlaybackState::playing(75.4, Some(200.));
+/// let mut program = Program::new();
+/// program.parse("%playback_time% / -%playback_time_remaining%").unwrap();
+/// let track = HashMap::new();
+/// let mut env = program.environment(&track);
+/// env.set_playback(state);
+/// assert_eq!(program.run_with_env(&mut env).unwrap(), "1:15 / -2:05");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlaybackState {
+    pub playing: bool,
+    pub paused: bool,
+    /// The position in the current track in seconds
+    pub time: f64,
+    /// The length of the current track in seconds if it is known.  Otherwise
+    /// the length is taken from the technical information of the track.
+    pub length: Option<f64>,
+}
+
+impl PlaybackState {
+    /// Constructs a new `PlaybackState` for a track that is playing at `time`
+    pub fn playing(time: f64, length: Option<f64>) -> Self {
+        PlaybackState {
+            playing: true,
+            paused: false,
+            time,
+            length,
+        }
+    }
+
+    /// Constructs a new `PlaybackState` for a track that is paused at `time`
+    pub fn paused(time: f64, length: Option<f64>) -> Self {
+        PlaybackState {
+            paused: true,
+            ..Self::playing(time, length)
+        }
+    }
+
+    /// Constructs a new `PlaybackState` when nothing is playing
+    pub fn stopped() -> Self {
+        Self::default()
+    }
+}
+
+/* %isplaying%, %ispaused%, %playback_time%, %playback_time_seconds%,
+ * %playback_time_remaining% and %playback_time_remaining_seconds% */
+pub fn field(env: &Environment, key: &str) -> Option<Value> {
+    let state = env.playback().unwrap_or_default();
+    /* like foobar2000, everything is false and the times are undefined when
+     * nothing is playing */
+    let flag = |set: bool| {
+        if set {
+            value_string("1", true)
+        } else {
+            value_string("", false)
+        }
+    };
+    let time = |secs: Option<f64>, format: fn(u64) -> String| match secs {
+        Some(secs) if state.playing => value_string(&format(secs.max(0.) as u64), true),
+        _ => value_string("?", false),
+    };
+    let remaining = || {
+        state
+            .length
+            .or_else(|| length_seconds(env))
+            .map(|length| (length - state.time).ceil())
+    };
+
+    match key {
+        "isplaying" => Some(flag(state.playing)),
+        "ispaused" => Some(flag(state.playing && state.paused)),
+        "playback_time" => Some(time(Some(state.time), format_time)),
+        "playback_time_seconds" => Some(time(Some(state.time), |s| s.to_string())),
+        "playback_time_remaining" => Some(time(remaining(), format_time)),
+        "playback_time_remaining_seconds" => Some(time(remaining(), |s| s.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(state: Option<PlaybackState>) -> Environment<'static> {
+        let mut env = Environment::new(HashMap::new());
+        if let Some(state) = state {
+            env.set_playback(state);
+        }
+        env
+    }
+
+    #[test]
+    fn test_stopped() {
+        for e in [env(None), env(Some(PlaybackState::stopped()))] {
+            assert_eq!(field(&e, "isplaying"), Some(value_string("", false)));
+            assert_eq!(field(&e, "ispaused"), Some(value_string("", false)));
+            assert_eq!(field(&e, "playback_time"), Some(value_string("?", false)));
+            assert_eq!(
+                field(&e, "playback_time_remaining"),
+                Some(value_string("?", false))
+            );
+        }
+    }
+
+    #[test]
+    fn test_playing() {
+        let e = env(Some(PlaybackState::playing(3725.9, Some(4000.))));
+        assert_eq!(field(&e, "isplaying"), Some(value_string("1", true)));
+        assert_eq!(field(&e, "ispaused"), Some(value_string("", false)));
+        assert_eq!(
+            field(&e, "playback_time"),
+            Some(value_string("1:02:05", true))
+        );
+        assert_eq!(
+            field(&e, "playback_time_seconds"),
+            Some(value_string("3725", true))
+        );
+        assert_eq!(
+            field(&e, "playback_time_remaining"),
+            Some(value_string("4:35", true))
+        );
+        assert_eq!(
+            field(&e, "playback_time_remaining_seconds"),
+            Some(value_string("275", true))
+        );
+    }
+
+    #[test]
+    fn test_paused() {
+        let e = env(Some(PlaybackState::paused(1., None)));
+        assert_eq!(field(&e, "isplaying"), Some(value_string("1", true)));
+        assert_eq!(field(&e, "ispaused"), Some(value_string("1", true)));
+        /* unknown length */
+        assert_eq!(
+            field(&e, "playback_time_remaining"),
+            Some(value_string("?", false))
+        );
+    }
+
+    #[test]
+    fn test_length_from_info() {
+        let mut e = env(Some(PlaybackState::playing(10., None)));
+        let mut info = HashMap::new();
+        info.insert(String::from("length_seconds"), String::from("70"));
+        e.set_info(info);
+        assert_eq!(
+            field(&e, "playback_time_remaining"),
+            Some(value_string("1:00", true))
+        );
+    }
+}
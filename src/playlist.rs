@@ -0,0 +1,135 @@
+use crate::environment::value_string;
+use crate::environment::{Environment, Value};
+
+/// The playlist (or any other list of tracks) a track is formatted as part
+/// of.  Holds the information shared by all the tracks of the list, the
+/// position of each track is set separately with
+/// [`Environment::set_list_position`].
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::playlist::Playlist;
+/// # use titleformat_rs::program::Program;
+/// # use std::collections::HashMap;
+/// let playlist = Playlist::new("Favourites");
+/// let mut program = Program::new();
+/// program.parse("%playlist_name% %list_index%/%list_total%").unwrap();
+/// let tracks = vec![HashMap::new(); 10];
+/// let results = program.run_playlist(&tracks, &playlist);
+/// assert_eq!(results[2], Ok(String::from("Favourites 03/10")));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Playlist {
+    pub name: String,
+    /// The playback queue as 1-based list indices, in queue order
+    pub queue: Vec<usize>,
+}
+
+impl Playlist {
+    /// Constructs a new `Playlist` called `name` with an empty queue
+    pub fn new(name: &str) -> Self {
+        Playlist {
+            name: String::from(name),
+            queue: vec![],
+        }
+    }
+}
+
+/// The position of a track within a list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListPosition {
+    /// 1-based index of the track in the list
+    pub index: usize,
+    /// number of tracks in the list
+    pub total: usize,
+}
+
+/* %list_index%, %list_total%, %playlist_name%, %queue_index%,
+ * %queue_indexes% and %queue_total% */
+pub fn field(env: &Environment, key: &str) -> Option<Value> {
+    match key {
+        "list_index" => Some(match env.list_position() {
+            /* padded to the width of %list_total% */
+            Some(pos) => value_string(
+                &format!("{:0width$}", pos.index, width = pos.total.to_string().len()),
+                true,
+            ),
+            None => value_string("?", false),
+        }),
+        "list_total" => Some(match env.list_position() {
+            Some(pos) => value_string(&pos.total.to_string(), true),
+            None => value_string("?", false),
+        }),
+        "playlist_name" => Some(match env.playlist() {
+            Some(playlist) => value_string(&playlist.name, true),
+            None => value_string("?", false),
+        }),
+        "queue_index" | "queue_indexes" | "queue_total" => Some(queue_field(env, key)),
+        _ => None,
+    }
+}
+
+/* the queue fields are only defined for tracks that are queued */
+fn queue_field(env: &Environment, key: &str) -> Value {
+    let (Some(playlist), Some(pos)) = (env.playlist(), env.list_position()) else {
+        return value_string("?", false);
+    };
+    let indexes: Vec<String> = playlist
+        .queue
+        .iter()
+        .enumerate()
+        .filter(|(_, index)| **index == pos.index)
+        .map(|(i, _)| (i + 1).to_string())
+        .collect();
+    if indexes.is_empty() {
+        return value_string("?", false);
+    }
+    match key {
+        "queue_index" => value_string(&indexes[0], true),
+        "queue_indexes" => value_string(&indexes.join(","), true),
+        _ => value_string(&playlist.queue.len().to_string(), true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_no_playlist() {
+        let env = Environment::new(HashMap::new());
+        assert_eq!(field(&env, "list_index"), Some(value_string("?", false)));
+        assert_eq!(field(&env, "list_total"), Some(value_string("?", false)));
+        assert_eq!(field(&env, "playlist_name"), Some(value_string("?", false)));
+        assert_eq!(field(&env, "queue_index"), Some(value_string("?", false)));
+        assert_eq!(field(&env, "artist"), None);
+    }
+
+    #[test]
+    fn test_list_position() {
+        let mut env = Environment::new(HashMap::new());
+        env.set_list_position(7, 120);
+        assert_eq!(field(&env, "list_index"), Some(value_string("007", true)));
+        assert_eq!(field(&env, "list_total"), Some(value_string("120", true)));
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut playlist = Playlist::new("p");
+        playlist.queue = vec![3, 1, 3];
+        let mut env = Environment::new(HashMap::new());
+        env.set_playlist(&playlist);
+        env.set_list_position(3, 5);
+        assert_eq!(field(&env, "queue_index"), Some(value_string("1", true)));
+        assert_eq!(
+            field(&env, "queue_indexes"),
+            Some(value_string("1,3", true))
+        );
+        assert_eq!(field(&env, "queue_total"), Some(value_string("3", true)));
+        env.set_list_position(2, 5);
+        assert_eq!(field(&env, "queue_index"), Some(value_string("?", false)));
+        assert_eq!(field(&env, "queue_total"), Some(value_string("?", false)));
+    }
+}
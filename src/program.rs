@@ -1,13 +1,33 @@
-use crate::environment::{value_string, Environment, Value};
-use crate::parser;
+use crate::dialect::{Dialect, LazyFn};
+use crate::edit::{self, Edit};
+use crate::environment::{value_string, Environment, Registry, Value};
+use crate::path::PathMode;
+use crate::playlist::Playlist;
+use crate::remap::Remapping;
+use crate::track::Track;
 use crate::types::Error;
 use crate::types::Expr;
 use crate::types::Expr::*;
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
 
-#[derive(Debug, Default)]
+/* A parsed program is immutable once parsed and only shares read-only data
+ * so it is Send + Sync and cheap to clone.  All the per-evaluation state
+ * lives in the `Environment` created for each run. */
+#[derive(Clone, Debug)]
 pub struct Program {
-    instr: Vec<Expr>,
+    instr: Arc<Vec<Expr>>,
+    funcs: Arc<Registry>,
+    remap: Arc<Remapping>,
+    dialect: Dialect,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Program {
@@ -19,10 +39,61 @@ impl Program {
     /// let program = Program::new();
     /// ```
     pub fn new() -> Self {
-        Program { instr: vec![] }
+        Self::with_registry(Arc::new(Registry::new()))
+    }
+
+    /// Constructs a new Program that calls functions from `registry`
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::environment::Registry;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::sync::Arc;
+    /// let registry = Arc::new(Registry::new());
+    /// let program = Program::with_registry(registry.clone());
+    /// ```
+    pub fn with_registry(funcs: Arc<Registry>) -> Self {
+        Program {
+            instr: Arc::new(vec![]),
+            funcs,
+            remap: Arc::new(Remapping::new()),
+            dialect: Dialect::Foobar2000,
+        }
+    }
+
+    /// Sets the table of fallbacks used when looking up fields
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use titleformat_rs::remap::Remapping;
+    /// # use std::collections::HashMap;
+    /// let mut remap = Remapping::new();
+    /// remap.alias("year", &["date"]);
+    /// let mut program = Program::new();
+    /// program.set_remapping(remap);
+    /// program.parse("%year%").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("date".into(), vec!["1999".into()]);
+    /// assert_eq!(program.run_with_meta(metadata).unwrap(), "1999");
+    /// ```
+    pub fn set_remapping(&mut self, remap: Remapping) {
+        self.remap = Arc::new(remap);
+    }
+
+    /// Sets the dialect the program is parsed and evaluated as, foobar2000
+    /// by default.  A program is parsed as the dialect set at the time, so
+    /// this comes before [`Program::parse`].
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
     }
 
-    /// Parses a program string
+    /// The dialect the program is evaluated as
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Parses a program string as written in the dialect of the program
     ///
     /// # Examples
     /// ```
@@ -31,7 +102,7 @@ impl Program {
     /// assert_eq!(program.parse("[%artist%]").unwrap(), ());
     /// ```
     pub fn parse(&mut self, instr: &str) -> Result<(), Error> {
-        self.instr = parser::parse(instr)?;
+        self.instr = Arc::new(self.dialect.parse(instr)?);
         Ok(())
     }
 
@@ -44,7 +115,7 @@ impl Program {
     /// assert_eq!(program.parse("[%artist%]").unwrap(), ());
     /// assert_eq!(program.run().unwrap(), String::from(""));
     /// ```
-    pub fn run(&mut self) -> Result<String, Error> {
+    pub fn run(&self) -> Result<String, Error> {
         self.run_with_meta(HashMap::new())
     }
 
@@ -61,22 +132,483 @@ impl Program {
     /// assert_eq!(program.run_with_meta(metadata).unwrap(), String::from("Happy"));
     /// ```
     pub fn run_with_meta(&self, metadata: HashMap<String, Vec<String>>) -> Result<String, Error> {
-        let mut env = Environment::new(metadata);
-        let result = match self.resolve_arg_vec(&mut env, &self.instr)? {
-            ExprValue(v) => v.val.clone(),
+        let mut env = Environment::with_registry(metadata, &self.funcs);
+        env.set_remapping(&self.remap);
+        self.run_with_env(&mut env)
+    }
+
+    /// Executes a program once for each set of metadata in `tracks`
+    ///
+    /// The function registry is shared between all the evaluations and the
+    /// metadata is only borrowed.  Every track gets its own result so a
+    /// failure for one track does not prevent formatting the others.
+    /// `%list_index%` and `%list_total%` are set from the position of each
+    /// track in `tracks`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("[%artist%]").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["Happy".into()]);
+    /// let tracks = vec![metadata, HashMap::new()];
+    /// let results = program.run_batch(&tracks);
+    /// assert_eq!(results[0], Ok(String::from("Happy")));
+    /// assert_eq!(results[1], Ok(String::from("")));
+    /// ```
+    pub fn run_batch<'t, T, I>(&self, tracks: I) -> Vec<Result<String, Error>>
+    where
+        T: Track + 't,
+        I: IntoIterator<Item = &'t T>,
+    {
+        let tracks: Vec<&T> = tracks.into_iter().collect();
+        let total = tracks.len();
+        tracks
+            .into_iter()
+            .enumerate()
+            .map(|(i, track)| {
+                let mut env = self.environment(track);
+                env.set_list_position(i + 1, total);
+                self.run_with_env(&mut env)
+            })
+            .collect()
+    }
+
+    /// Executes a program once for each track of `playlist`, with the
+    /// metadata of each in `tracks`
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::playlist::Playlist;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut playlist = Playlist::new("Queue");
+    /// playlist.queue = vec![2];
+    /// let mut program = Program::new();
+    /// program.parse("%list_index%:[%queue_index%]").unwrap();
+    /// let tracks = vec![HashMap::new(); 2];
+    /// let results = program.run_playlist(&tracks, &playlist);
+    /// assert_eq!(results, vec![Ok(String::from("1:")), Ok(String::from("2:1"))]);
+    /// ```
+    pub fn run_playlist<T: Track>(
+        &self,
+        tracks: &[T],
+        playlist: &Playlist,
+    ) -> Vec<Result<String, Error>> {
+        tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                let mut env = self.environment(track);
+                env.set_playlist(playlist);
+                env.set_list_position(i + 1, tracks.len());
+                self.run_with_env(&mut env)
+            })
+            .collect()
+    }
+
+    /// Executes a program once for a whole group of tracks, e.g. an album.
+    /// The aggregate functions (`$group_sum()`, `$group_common()`, ...) and
+    /// fields (`%group_length%`, `%group_count%`, ...) work over all of
+    /// `tracks`, any other field is taken from the first track.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use titleformat_rs::track::TrackData;
+    /// let mut program = Program::new();
+    /// program
+    ///     .parse("%album% ($group_common(artist,Various Artists)): %group_count% tracks, %group_length%")
+    ///     .unwrap();
+    /// let tracks: Vec<TrackData> = [("A", "200"), ("B", "100")]
+    ///     .iter()
+    ///     .map(|(artist, length)| {
+    ///         let mut track = TrackData::default();
+    ///         track.metadata.insert("album".into(), vec!["Mix".into()]);
+    ///         track.metadata.insert("artist".into(), vec![artist.to_string()]);
+    ///         track.info.insert("length_seconds".into(), length.to_string());
+    ///         track
+    ///     })
+    ///     .collect();
+    /// assert_eq!(
+    ///     program.run_group(&tracks).unwrap(),
+    ///     "Mix (Various Artists): 2 tracks, 5:00"
+    /// );
+    /// ```
+    pub fn run_group<T: Track>(&self, tracks: &[T]) -> Result<String, Error> {
+        let group: Vec<Environment> = tracks.iter().map(|t| self.environment(t)).collect();
+        let empty = HashMap::new();
+        let mut env = match tracks.first() {
+            Some(track) => self.environment(track),
+            None => self.environment(&empty),
+        };
+        env.set_group(&group);
+        self.run_with_env(&mut env)
+    }
+
+    /// Runs a script changing the tags of `track` with `$set()`,
+    /// `$append()`, `$unset()` and, in the Picard dialect, `$copy()` and
+    /// `$copymerge()`.  The output of the script is ignored and `track` is
+    /// left untouched, the edited tags are returned with what changed.
+    /// Hidden variables of the dialect are not tags and are left out.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::edit::Change;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("$set(album artist,%artist%)$append(genre,Live)$unset(comment)").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["Queen".into()]);
+    /// metadata.insert("comment".into(), vec!["ripped".into()]);
+    /// let edit = program.run_edit(&metadata).unwrap();
+    /// assert_eq!(edit.metadata["album artist"], vec!["Queen"]);
+    /// assert_eq!(
+    ///     edit.changes,
+    ///     vec![
+    ///         Change::Added("album artist".into()),
+    ///         Change::Removed("comment".into()),
+    ///         Change::Added("genre".into()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn run_edit<T: Track>(&self, track: &T) -> Result<Edit, Error> {
+        let mut env = self.environment(track);
+        env.set_editing(true);
+        self.evaluate_with_env(&mut env)?;
+        let tags = |metadata: &HashMap<String, Vec<String>>| -> HashMap<String, Vec<String>> {
+            metadata
+                .iter()
+                .filter(|(k, _)| !self.dialect.is_hidden(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        };
+        let mut metadata = tags(env.metadata());
+        let changes = edit::changes(&tags(track.metadata()), &metadata);
+        /* hidden variables the track came with are kept as they were */
+        for (k, v) in track.metadata() {
+            if self.dialect.is_hidden(k) {
+                metadata.insert(k.clone(), v.clone());
+            }
+        }
+        Ok(Edit { metadata, changes })
+    }
+
+    /// Executes a program to build a file path for `track`.  The values of
+    /// fields are sanitized with `mode`, also where they are passed to a
+    /// function, while the literal `/` and `\` of the program separate
+    /// directories.  The names are then made valid and fit in the length
+    /// limits with [`PathMode::finish`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::path::PathMode;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("%album%/$upper(%title%)").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("album".into(), vec!["1/2".into()]);
+    /// metadata.insert("title".into(), vec!["a/b".into()]);
+    /// assert_eq!(program.run_path(&metadata, &PathMode::linux()).unwrap(), "1_2/A_B");
+    /// ```
+    pub fn run_path<T: Track>(&self, track: &T, mode: &PathMode) -> Result<String, Error> {
+        let mut env = self.environment(track);
+        env.set_path_mode(mode);
+        Ok(mode.finish(&self.run_with_env(&mut env)?))
+    }
+
+    /// Executes a program once for each set of metadata in `tracks` spreading
+    /// the work over a thread pool.  The results are in the same order as
+    /// `tracks`, which can be given the same way as to
+    /// [`Program::run_batch`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("$add(%n%,1)").unwrap();
+    /// let tracks: Vec<_> = (0..100)
+    ///     .map(|i| HashMap::from([("n".to_string(), vec![i.to_string()])]))
+    ///     .collect();
+    /// let results = program.par_run_batch(&tracks);
+    /// assert_eq!(results[99], Ok(String::from("100")));
+    /// ```
+    #[cfg(feature = "parallel")]
+    pub fn par_run_batch<'t, T, I>(&self, tracks: I) -> Vec<Result<String, Error>>
+    where
+        T: Track + Sync + 't,
+        I: rayon::iter::IntoParallelIterator<Item = &'t T>,
+        I::Iter: rayon::iter::IndexedParallelIterator,
+    {
+        use rayon::prelude::*;
+
+        let tracks = tracks.into_par_iter();
+        let total = tracks.len();
+        tracks
+            .enumerate()
+            .map(|(i, track)| {
+                let mut env = self.environment(track);
+                env.set_list_position(i + 1, total);
+                self.run_with_env(&mut env)
+            })
+            .collect()
+    }
+
+    /// Executes a program with associated metadata writing the output
+    /// directly into `w`.  Literal text and each top-level field or function
+    /// are written as soon as they are evaluated so no intermediate string
+    /// for the whole output is built.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("%artist% - %title%;").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["Happy".into()]);
+    /// metadata.insert("title".into(), vec!["Song".into()]);
+    /// let mut playlist = String::new();
+    /// program.write_to(&mut playlist, &metadata).unwrap();
+    /// program.write_to(&mut playlist, &metadata).unwrap();
+    /// assert_eq!(playlist, "Happy - Song;Happy - Song;");
+    /// ```
+    pub fn write_to<W: fmt::Write, T: Track>(&self, w: &mut W, metadata: &T) -> Result<(), Error> {
+        let mut env = self.environment(metadata);
+        self.stream(&mut env, |s| {
+            w.write_str(s).map_err(|e| Error::WriteError(e.to_string()))
+        })
+    }
+
+    /// Executes a program with associated metadata writing the output
+    /// directly into the `io::Write` sink `w`, e.g. a file or socket.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("%title%,%artist%").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["Happy".into()]);
+    /// metadata.insert("title".into(), vec!["Song".into()]);
+    /// let mut csv = Vec::new();
+    /// program.write_io(&mut csv, &metadata).unwrap();
+    /// assert_eq!(csv, b"Song,Happy");
+    /// ```
+    pub fn write_io<W: io::Write, T: Track>(&self, w: &mut W, metadata: &T) -> Result<(), Error> {
+        let mut env = self.environment(metadata);
+        self.stream(&mut env, |s| {
+            w.write_all(s.as_bytes())
+                .map_err(|e| Error::WriteError(e.to_string()))
+        })
+    }
+
+    /* evaluates the top-level expressions one at a time passing each piece
+     * of output to sink */
+    fn stream<F>(&self, env: &mut Environment, mut sink: F) -> Result<(), Error>
+    where
+        F: FnMut(&str) -> Result<(), Error>,
+    {
+        for expr in self.instr.iter() {
+            match expr {
+                Literal(v) => sink(v)?,
+                _ => sink(&self.resolve_output(env, std::slice::from_ref(expr))?.val)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates a program with associated metadata returning both the
+    /// output and its truth value
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("%artist%").unwrap();
+    /// let value = program.evaluate(&HashMap::new()).unwrap();
+    /// assert_eq!(value.val, "?");
+    /// assert!(!value.cond);
+    /// ```
+    pub fn evaluate<T: Track>(&self, metadata: &T) -> Result<Value, Error> {
+        let mut env = self.environment(metadata);
+        self.evaluate_with_env(&mut env)
+    }
+
+    /// Returns the truth value of a program with associated metadata.  Useful
+    /// for filtering tracks with a titleformat expression.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("$and(%rating%,$greater(%rating%,3))").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// assert!(!program.matches(&metadata).unwrap());
+    /// metadata.insert("rating".into(), vec!["4".into()]);
+    /// assert!(program.matches(&metadata).unwrap());
+    /// ```
+    pub fn matches<T: Track>(&self, metadata: &T) -> Result<bool, Error> {
+        Ok(self.evaluate(metadata)?.cond)
+    }
+
+    /// Executes a program once for every combination of values of the
+    /// multi-value fields (`%<field>%`) it uses.  A track with the artists
+    /// "A" and "B" formatted with `%<artist>%` produces both "A" and "B".
+    /// Programs without any `%<field>%` produce exactly one output.
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.parse("%<artist>% - %<genre>%").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["A".into(), "B".into()]);
+    /// metadata.insert("genre".into(), vec!["Rock".into(), "Pop".into()]);
+    /// assert_eq!(
+    ///     program.run_multi(&metadata).unwrap(),
+    ///     vec!["A - Rock", "A - Pop", "B - Rock", "B - Pop"]
+    /// );
+    /// ```
+    pub fn run_multi<T: Track>(&self, metadata: &T) -> Result<Vec<String>, Error> {
+        let mut env = self.environment(metadata);
+        self.run_multi_with_env(&mut env)
+    }
+
+    /* evaluates the program for the cross product of all the values of the
+     * %<field>%s in the program */
+    fn run_multi_with_env(&self, env: &mut Environment) -> Result<Vec<String>, Error> {
+        let mut fields = vec![];
+        multi_variables(&self.instr, &mut fields);
+        /* missing fields still produce a single (false) output */
+        let counts: Vec<usize> = fields.iter().map(|f| env.meta_num(f).max(1)).collect();
+        let mut indices = vec![0; fields.len()];
+        let mut ret = vec![];
+
+        loop {
+            for (field, i) in fields.iter().zip(indices.iter()) {
+                env.select_value(field, *i);
+            }
+            ret.push(self.run_with_env(env)?);
+
+            /* advance the last field first so that the output is ordered by
+             * the first field */
+            let mut pos = fields.len();
+            loop {
+                if pos == 0 {
+                    return Ok(ret);
+                }
+                pos -= 1;
+                indices[pos] += 1;
+                if indices[pos] < counts[pos] {
+                    break;
+                }
+                indices[pos] = 0;
+            }
+        }
+    }
+
+    /// Constructs a new `Environment` for evaluating this program for `track`.
+    /// Additional context, e.g. the playlist, can be attached to the
+    /// environment before evaluating it with [`Program::run_with_env`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::playlist::Playlist;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let playlist = Playlist::new("Favourites");
+    /// let mut program = Program::new();
+    /// program.parse("%playlist_name%: %list_index%").unwrap();
+    /// let track = HashMap::new();
+    /// let mut env = program.environment(&track);
+    /// env.set_playlist(&playlist);
+    /// env.set_list_position(5, 10);
+    /// assert_eq!(program.run_with_env(&mut env).unwrap(), "Favourites: 05");
+    /// ```
+    pub fn environment<'a, T: Track>(&'a self, track: &'a T) -> Environment<'a> {
+        let mut env = Environment::borrowed(track, &self.funcs);
+        env.set_remapping(&self.remap);
+        env
+    }
+
+    /// Executes a program within `env`
+    pub fn run_with_env(&self, env: &mut Environment) -> Result<String, Error> {
+        Ok(self.evaluate_with_env(env)?.val)
+    }
+
+    /// Evaluates a program within `env` returning both the output and its
+    /// truth value
+    pub fn evaluate_with_env(&self, env: &mut Environment) -> Result<Value, Error> {
+        self.resolve_output(env, &self.instr)
+    }
+
+    /* evaluates expressions parsed elsewhere, e.g. the operands of a query,
+     * with the functions and remapping of this program */
+    pub(crate) fn evaluate_exprs(
+        &self,
+        env: &mut Environment,
+        exprs: &[Expr],
+    ) -> Result<Value, Error> {
+        match self.resolve_arg_vec(env, exprs)? {
+            ExprValue(v) => Ok(v),
             _ => unreachable!(),
+        }
+    }
+
+    /* resolves the expressions making up the output, which in path mode
+     * sanitizes the values of fields but not the literals, also inside the
+     * arguments of functions.  The functions of a dialect evaluate their
+     * arguments themselves, maybe repeatedly, so their results are
+     * sanitized as a whole */
+    fn resolve_output(&self, env: &mut Environment, exprs: &[Expr]) -> Result<Value, Error> {
+        let Some(mode) = env.path_mode() else {
+            return self.evaluate_exprs(env, exprs);
         };
-        Ok(result)
+        let mut out = value_string("", false);
+        for expr in exprs {
+            let value = match expr {
+                Literal(_) | ExprValue(_) => self.eval(env, expr)?,
+                Conditional(args) => match self.resolve_output(env, args)? {
+                    Value { val, cond: true } => value_string(&val, true),
+                    _ => value_string("", false),
+                },
+                FuncCall(name, args) if self.dialect_function(env, name).is_none() => {
+                    let mut resolved = Vec::with_capacity(args.len());
+                    for arg in args {
+                        resolved.push(vec![ExprValue(self.resolve_output(env, arg)?)]);
+                    }
+                    self.eval(env, &FuncCall(name.clone(), resolved))?
+                }
+                _ => {
+                    let value = self.eval(env, expr)?;
+                    value_string(&mode.sanitize(&value.val), value.cond)
+                }
+            };
+            out.val.push_str(&value.val);
+            /* picard does an or here */
+            out.cond = out.cond || value.cond;
+        }
+        Ok(out)
     }
 
     /* resolves a set of expressions into a single resolved value
      * e.g. '%artist%literal' with artist=best would resolve to 'bestliteral' */
-    fn resolve_arg_vec(&self, env: &mut Environment, args: &Vec<Expr>) -> Result<Expr, Error> {
+    fn resolve_arg_vec(&self, env: &mut Environment, args: &[Expr]) -> Result<Expr, Error> {
         let mut new_arg = value_string("", false);
 
         for arg in args {
             let tmp = self.eval(env, arg)?;
-            new_arg.val = new_arg.val + &tmp.val;
+            new_arg.val.push_str(&tmp.val);
             /* picard does an or here */
             new_arg.cond = new_arg.cond || tmp.cond;
         }
@@ -84,12 +616,26 @@ impl Program {
         Ok(ExprValue(new_arg))
     }
 
+    /* the function of the dialect called name, unless one was inserted
+     * into the registry */
+    fn dialect_function(&self, env: &Environment, name: &str) -> Option<LazyFn> {
+        if self.funcs.is_inserted(name) {
+            return None;
+        }
+        self.dialect.function(name, env.is_editing())
+    }
+
     fn eval(&self, env: &mut Environment, expr: &Expr) -> Result<Value, Error> {
+        Ok(self.dialect.truth(expr, self.eval_expr(env, expr)?))
+    }
+
+    fn eval_expr(&self, env: &mut Environment, expr: &Expr) -> Result<Value, Error> {
         match expr {
             ExprValue(v) => Ok(v.clone()),
             /* literals are always true for conditionals */
             Literal(v) => Ok(value_string(v, true)),
-            Variable(var) => Ok(env.get_variable(var)),
+            Variable(var) => Ok(self.dialect.variable(env, var)),
+            MultiVariable(var) => Ok(env.get_multi_variable(var)),
             Conditional(args) => {
                 let arg = self.resolve_arg_vec(env, args)?;
                 match self.eval(env, &arg)? {
@@ -98,6 +644,9 @@ impl Program {
                 }
             }
             FuncCall(name, args) => {
+                if let Some(func) = self.dialect_function(env, name) {
+                    return func(self, env, args);
+                }
                 let mut evaluated_args = Vec::new();
                 for unresolved in args {
                     let resolved = self.resolve_arg_vec(env, unresolved)?;
@@ -110,9 +659,26 @@ impl Program {
     }
 }
 
+/* collects the names of all the %<field>%s used in exprs in order of first use */
+fn multi_variables(exprs: &[Expr], fields: &mut Vec<String>) {
+    for expr in exprs {
+        match expr {
+            MultiVariable(var) if !fields.contains(var) => fields.push(var.clone()),
+            Conditional(args) => multi_variables(args, fields),
+            FuncCall(_, args) => {
+                for arg in args {
+                    multi_variables(arg, fields);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::edit::Change;
 
     #[test]
     fn test_parse() {
@@ -215,6 +781,195 @@ mod tests {
         assert_eq!(prog.run().unwrap(), String::from(""));
     }
 
+    #[test]
+    fn test_run_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Program>();
+
+        let mut prog = Program::new();
+        prog.parse("$add(%a%,2)").unwrap();
+        let prog = Arc::new(prog);
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let prog = prog.clone();
+                std::thread::spawn(move || {
+                    let mut m = HashMap::new();
+                    m.insert(String::from("a"), vec![i.to_string()]);
+                    prog.run_with_meta(m).unwrap()
+                })
+            })
+            .collect();
+        let results: Vec<String> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(results, vec!["2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn test_run_batch() {
+        let mut prog = Program::new();
+        prog.parse("$add(%a%,2)").unwrap();
+        let tracks: Vec<_> = (0..3)
+            .map(|i| {
+                let mut m = HashMap::new();
+                m.insert(String::from("a"), vec![i.to_string()]);
+                m
+            })
+            .collect();
+        assert_eq!(
+            prog.run_batch(&tracks),
+            vec![
+                Ok(String::from("2")),
+                Ok(String::from("3")),
+                Ok(String::from("4"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_batch_list_index() {
+        let mut prog = Program::new();
+        prog.parse("$num(%list_index%,$len(%list_total%))").unwrap();
+        let tracks = vec![HashMap::new(); 12];
+        let results = prog.run_batch(&tracks);
+        assert_eq!(results[0], Ok(String::from("01")));
+        assert_eq!(results[11], Ok(String::from("12")));
+    }
+
+    #[test]
+    fn test_run_batch_error() {
+        let mut prog = Program::new();
+        prog.parse("$mul(%a%,2)").unwrap();
+        let mut big = HashMap::new();
+        big.insert(String::from("a"), vec![i64::MAX.to_string()]);
+        let mut small = HashMap::new();
+        small.insert(String::from("a"), vec![String::from("2")]);
+        let results = prog.run_batch(vec![&big, &small]);
+        assert_eq!(results[0], Err(Error::OutOfRange));
+        assert_eq!(results[1], Ok(String::from("4")));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_run_batch() {
+        let mut prog = Program::new();
+        prog.parse("$add(%a%,2)").unwrap();
+        let tracks: Vec<_> = (0..1000)
+            .map(|i| {
+                let mut m = HashMap::new();
+                m.insert(String::from("a"), vec![i.to_string()]);
+                m
+            })
+            .collect();
+        assert_eq!(prog.par_run_batch(&tracks), prog.run_batch(&tracks));
+        let refs: Vec<_> = tracks.iter().rev().collect();
+        assert_eq!(prog.par_run_batch(refs.clone()), prog.run_batch(refs));
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut prog = Program::new();
+        prog.parse("%b%-$add(%b%,1)").unwrap();
+        let mut m = HashMap::new();
+        m.insert(String::from("b"), vec![String::from("2")]);
+        let mut out = String::from("start:");
+        prog.write_to(&mut out, &m).unwrap();
+        assert_eq!(out, String::from("start:2-3"));
+        assert_eq!(out[6..], prog.run_with_meta(m).unwrap());
+    }
+
+    #[test]
+    fn test_write_io_error() {
+        struct Broken;
+        impl io::Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut prog = Program::new();
+        prog.parse("a").unwrap();
+        assert_eq!(
+            prog.write_io(&mut Broken, &HashMap::new()),
+            Err(Error::WriteError(
+                io::Error::from(io::ErrorKind::BrokenPipe).to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut prog = Program::new();
+        prog.parse("%a%").unwrap();
+        let mut m = HashMap::new();
+        assert_eq!(prog.evaluate(&m).unwrap(), value_string("?", false));
+        m.insert(String::from("a"), vec![String::from("val")]);
+        assert_eq!(prog.evaluate(&m).unwrap(), value_string("val", true));
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut prog = Program::new();
+        prog.parse("$and(%rating%,$greater(%rating%,3))").unwrap();
+        let mut m = HashMap::new();
+        assert!(!prog.matches(&m).unwrap());
+        m.insert(String::from("rating"), vec![String::from("3")]);
+        assert!(!prog.matches(&m).unwrap());
+        m.insert(String::from("rating"), vec![String::from("5")]);
+        assert!(prog.matches(&m).unwrap());
+    }
+
+    #[test]
+    fn test_run_multi() {
+        let mut prog = Program::new();
+        prog.parse("[%<a>%|]$upper(%<b>%)/%<a>%").unwrap();
+        let mut m = HashMap::new();
+        m.insert(
+            String::from("a"),
+            vec![String::from("1"), String::from("2")],
+        );
+        m.insert(
+            String::from("b"),
+            vec![String::from("x"), String::from("y")],
+        );
+        assert_eq!(
+            prog.run_multi(&m).unwrap(),
+            vec!["1|X/1", "1|Y/1", "2|X/2", "2|Y/2"]
+        );
+    }
+
+    #[test]
+    fn test_run_multi_missing() {
+        let mut prog = Program::new();
+        prog.parse("[%<a>%]x").unwrap();
+        assert_eq!(prog.run_multi(&HashMap::new()).unwrap(), vec!["x"]);
+        prog.parse("%a%").unwrap();
+        assert_eq!(prog.run_multi(&HashMap::new()).unwrap(), vec!["?"]);
+    }
+
+    #[test]
+    fn test_run_multi_variable() {
+        let mut prog = Program::new();
+        prog.parse("%<a>%").unwrap();
+        let mut m = HashMap::new();
+        m.insert(
+            String::from("a"),
+            vec![String::from("1"), String::from("2")],
+        );
+        assert_eq!(prog.run_with_meta(m).unwrap(), String::from("1"));
+    }
+
+    #[test]
+    fn test_remapping() {
+        let mut prog = Program::new();
+        prog.parse("%album artist%|[%track artist%]").unwrap();
+        let mut m = HashMap::new();
+        m.insert(String::from("artist"), vec![String::from("A")]);
+        assert_eq!(prog.run_with_meta(m.clone()).unwrap(), String::from("A|"));
+        m.insert(String::from("album artist"), vec![String::from("V")]);
+        assert_eq!(prog.run_with_meta(m).unwrap(), String::from("V|A"));
+    }
+
     #[test]
     fn test_conditional_variable_literal() {
         let mut prog = Program::new();
@@ -223,4 +978,111 @@ mod tests {
         m.insert(String::from("a"), vec![String::from("2")]);
         assert_eq!(prog.run_with_meta(m).unwrap(), String::from("2b"));
     }
+
+    #[test]
+    fn test_run_group() {
+        let mut prog = Program::new();
+        prog.parse("%album%|$group_sum(n)|$group_distinct(n)|%group_count%")
+            .unwrap();
+        let tracks: Vec<HashMap<String, Vec<String>>> = ["1", "2", "2"]
+            .iter()
+            .map(|n| {
+                let mut m = HashMap::new();
+                m.insert(String::from("album"), vec![String::from("X")]);
+                m.insert(String::from("n"), vec![String::from(*n)]);
+                m
+            })
+            .collect();
+        assert_eq!(prog.run_group(&tracks).unwrap(), String::from("X|5|2|3"));
+        let empty: Vec<HashMap<String, Vec<String>>> = vec![];
+        assert_eq!(prog.run_group(&empty).unwrap(), String::from("?|?|0|0"));
+    }
+
+    #[test]
+    fn test_run_path() {
+        let mut prog = Program::new();
+        prog.parse("[%a%]/%b%\\$if(%c%,%c%,x/y)").unwrap();
+        let mut m = HashMap::new();
+        m.insert(String::from("b"), vec![String::from("b/c")]);
+        assert_eq!(
+            prog.run_path(&m, &PathMode::linux()).unwrap(),
+            String::from("b_c/x/y")
+        );
+        m.insert(String::from("a"), vec![String::from("a:")]);
+        assert_eq!(
+            prog.run_path(&m, &PathMode::windows()).unwrap(),
+            String::from("a_/b_c/x/y")
+        );
+        let mut lower = Program::new();
+        lower.parse("$lower(%b%/X)").unwrap();
+        assert_eq!(lower.run_path(&m, &PathMode::linux()).unwrap(), "b_c/x");
+        /* not sanitized outside of path mode */
+        assert_eq!(prog.run_with_meta(m).unwrap(), String::from("a:/b/c\\x/y"));
+    }
+
+    #[test]
+    fn test_edit_functions_only_in_run_edit() {
+        let mut prog = Program::new();
+        prog.parse("$set(a,b)%a%").unwrap();
+        assert!(matches!(prog.run(), Err(Error::UndefinedFunction(_))));
+        assert_eq!(prog.run_edit(&HashMap::new()).unwrap().metadata["a"], ["b"]);
+        prog.set_dialect(Dialect::Picard);
+        assert_eq!(prog.run().unwrap(), "b");
+    }
+
+    #[test]
+    fn test_run_edit() {
+        let mut m = HashMap::new();
+        m.insert(String::from("genre"), vec![String::from("Rock")]);
+        m.insert(String::from("title"), vec![String::from("t")]);
+        m.insert(String::from("_id"), vec![String::from("1")]);
+
+        let mut prog = Program::new();
+        prog.parse("$append(genre,Live)$set(title,$upper(%title%))$set(x,)")
+            .unwrap();
+        let edit = prog.run_edit(&m).unwrap();
+        assert_eq!(edit.metadata["genre"], vec!["Rock", "Live"]);
+        assert_eq!(edit.metadata["title"], vec!["T"]);
+        assert_eq!(
+            edit.changes,
+            vec![
+                Change::Changed(String::from("genre")),
+                Change::Changed(String::from("title"))
+            ]
+        );
+        /* the track itself is untouched */
+        assert_eq!(m["title"], vec!["t"]);
+
+        prog.set_dialect(Dialect::Picard);
+        prog.parse("$set(_tmp,1)$unset(_id)$copy(style,genre)$set(genre,%title%)")
+            .unwrap();
+        let edit = prog.run_edit(&m).unwrap();
+        assert_eq!(edit.metadata.get("_tmp"), None);
+        assert_eq!(edit.metadata["_id"], vec!["1"]);
+        assert_eq!(edit.metadata["style"], vec!["Rock"]);
+        assert_eq!(
+            edit.changes,
+            vec![
+                Change::Changed(String::from("genre")),
+                Change::Added(String::from("style"))
+            ]
+        );
+
+        prog.parse("no changes").unwrap();
+        assert!(!prog.run_edit(&m).unwrap().is_changed());
+    }
+
+    #[test]
+    fn test_inserted_function_before_dialect() {
+        fn mine(_args: Vec<String>) -> String {
+            String::from("MINE")
+        }
+        let mut registry = Registry::new();
+        registry.insert("set", crate::environment::FuncValue::NativeFn(mine));
+        let mut prog = Program::with_registry(Arc::new(registry));
+        prog.parse("$set(a,b)%a%").unwrap();
+        assert_eq!(prog.run().unwrap(), "MINE?");
+        prog.set_dialect(Dialect::Picard);
+        assert_eq!(prog.run().unwrap(), "MINE");
+    }
 }
@@ -0,0 +1,143 @@
+use iso_8601::{
+    AnyTime, ApproxAnyTime, ApproxDate, DateTime, GlobalTime, LocalTime, ODate, PartialDateTime,
+    YmdDate,
+};
+use std::str::FromStr;
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+
+/* days since 1970-01-01 of a date in the proleptic Gregorian calendar */
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/* the first second of a date and the first second after it, for dates with
+ * the precision of a day, month or year */
+fn date_range(date: &ApproxDate) -> Option<(i64, i64)> {
+    let (start, end) = match date {
+        ApproxDate::YMD(YmdDate { year, month, day }) => {
+            let start = days_from_civil(*year as i64, *month as i64, *day as i64);
+            (start, start + 1)
+        }
+        ApproxDate::YM(ym) => {
+            let (year, month) = (ym.year as i64, ym.month as i64);
+            let next = if month == 12 {
+                (year + 1, 1)
+            } else {
+                (year, month + 1)
+            };
+            (
+                days_from_civil(year, month, 1),
+                days_from_civil(next.0, next.1, 1),
+            )
+        }
+        ApproxDate::Y(y) => (
+            days_from_civil(y.year as i64, 1, 1),
+            days_from_civil(y.year as i64 + 1, 1, 1),
+        ),
+        ApproxDate::O(ODate { year, day }) => {
+            let start = days_from_civil(*year as i64, 1, 1) + *day as i64 - 1;
+            (start, start + 1)
+        }
+        /* weeks and centuries are not used for tags */
+        _ => return None,
+    };
+    Some((start * DAY, end * DAY))
+}
+
+/* seconds into the day, the precision and the offset from UTC in seconds */
+fn time_of_day(time: &ApproxAnyTime) -> (i64, i64, i64) {
+    fn split<N: iso_8601::NaiveTime>(time: &AnyTime<N>) -> (&LocalTime<N>, i64) {
+        match time {
+            AnyTime::Global(GlobalTime { local, timezone }) => (local, *timezone as i64 * MINUTE),
+            AnyTime::Local(local) => (local, 0),
+        }
+    }
+    match time {
+        ApproxAnyTime::HMS(t) => {
+            let (local, tz) = split(t);
+            let n = &local.naive;
+            (
+                n.hour as i64 * HOUR + n.minute as i64 * MINUTE + n.second as i64,
+                1,
+                tz,
+            )
+        }
+        ApproxAnyTime::HM(t) => {
+            let (local, tz) = split(t);
+            let n = &local.naive;
+            (n.hour as i64 * HOUR + n.minute as i64 * MINUTE, MINUTE, tz)
+        }
+        ApproxAnyTime::H(t) => {
+            let (local, tz) = split(t);
+            (local.naive.hour as i64 * HOUR, HOUR, tz)
+        }
+    }
+}
+
+/// Parses an ISO 8601 date, optionally with a time, into the range of
+/// seconds since the Unix epoch it covers: "1999" is the whole year and
+/// "1999-05-01 12:30" a single minute.  A space may separate the date and
+/// the time, as in foobar2000's `%added%`, and times without a timezone are
+/// taken as UTC.
+pub(crate) fn parse_range(s: &str) -> Option<(i64, i64)> {
+    let s = s.trim();
+    /* only four digit years, which also keeps iso_8601 from overflowing */
+    let bytes = s.as_bytes();
+    if bytes.len() < 4
+        || !bytes[..4].iter().all(u8::is_ascii_digit)
+        || matches!(bytes.get(4), Some(c) if c.is_ascii_digit())
+    {
+        return None;
+    }
+    let s = s.replacen(' ', "T", 1);
+    match PartialDateTime::from_str(&s).ok()? {
+        PartialDateTime::Date(date) => date_range(&date),
+        PartialDateTime::DateTime(DateTime { date, time }) => {
+            let (day, _) = date_range(&date)?;
+            let (seconds, precision, tz) = time_of_day(&time);
+            let start = day + seconds - tz;
+            Some((start, start + precision))
+        }
+        PartialDateTime::Time(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_days_from_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("1970"), Some((0, 365 * DAY)));
+        assert_eq!(parse_range("1970-02"), Some((31 * DAY, 59 * DAY)));
+        assert_eq!(parse_range("1970-12"), Some((334 * DAY, 365 * DAY)));
+        assert_eq!(parse_range("1970-01-02"), Some((DAY, 2 * DAY)));
+        assert_eq!(parse_range("1970-032"), Some((31 * DAY, 32 * DAY)));
+        assert_eq!(parse_range("1970-01-01 01:02:03"), Some((3723, 3724)));
+        assert_eq!(parse_range("1970-01-01T01:02"), Some((3720, 3720 + MINUTE)));
+        assert_eq!(
+            parse_range("1970-01-01T02:00+01:00"),
+            Some((HOUR, HOUR + MINUTE))
+        );
+        assert_eq!(parse_range("12:00"), None);
+        assert_eq!(parse_range("99999"), None);
+        assert_eq!(parse_range("soon"), None);
+        assert_eq!(parse_range(""), None);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::environment::{Environment, Registry};
+use crate::program::Program;
+use crate::query::date::parse_range;
+use crate::query::{Condition, Date, Operand, Query};
+use crate::remap::Remapping;
+use crate::track::Track;
+use crate::types::Error;
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Evaluates parsed queries against tracks
+///
+/// The titleformat operands are evaluated with the functions and field
+/// remapping of the evaluator, and the relative date conditions
+/// (`DURING LAST 2 WEEKS`) against its idea of now, which can be fixed with
+/// [`Evaluator::set_now`] for reproducible results.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::query::{self, Evaluator};
+/// # use std::collections::HashMap;
+/// let mut metadata = HashMap::new();
+/// metadata.insert("added".to_string(), vec!["1970-01-10 12:00:00".to_string()]);
+/// metadata.insert("date".to_string(), vec!["2001".to_string()]);
+/// let mut evaluator = Evaluator::new();
+/// /* 1970-01-14 00:00:00 UTC */
+/// evaluator.set_now(13 * 24 * 60 * 60);
+/// let query = query::parse("%added% DURING LAST 1 WEEK AND date AFTER 1999").unwrap();
+/// assert!(evaluator.matches(&query, &metadata).unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Evaluator {
+    program: Program,
+    now: i64,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+fn number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok()
+}
+
+impl Evaluator {
+    /// Constructs a new `Evaluator` using the current time as now
+    pub fn new() -> Self {
+        Self::with_registry(Arc::new(Registry::new()))
+    }
+
+    /// Constructs a new `Evaluator` that calls functions from `registry`
+    pub fn with_registry(funcs: Arc<Registry>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        Evaluator {
+            program: Program::with_registry(funcs),
+            now,
+        }
+    }
+
+    /// Sets the table of fallbacks used when looking up fields
+    pub fn set_remapping(&mut self, remap: Remapping) {
+        self.program.set_remapping(remap);
+    }
+
+    /// Sets now as seconds since the Unix epoch
+    pub fn set_now(&mut self, now: i64) {
+        self.now = now;
+    }
+
+    /// Returns whether `track` matches `query`
+    pub fn matches<T: Track>(&self, query: &Query, track: &T) -> Result<bool, Error> {
+        let mut env = self.program.environment(track);
+        self.eval(query, track, &mut env)
+    }
+
+    /// Returns the indices of the tracks matching `query`
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::query::{self, Evaluator};
+    /// # use std::collections::HashMap;
+    /// let tracks: Vec<HashMap<String, Vec<String>>> = ["2", "5", "4"]
+    ///     .iter()
+    ///     .map(|r| HashMap::from([("rating".to_string(), vec![r.to_string()])]))
+    ///     .collect();
+    /// let query = query::parse("%rating% GREATER 3").unwrap();
+    /// assert_eq!(Evaluator::new().filter(&query, &tracks).unwrap(), vec![1, 2]);
+    /// ```
+    pub fn filter<T: Track>(&self, query: &Query, tracks: &[T]) -> Result<Vec<usize>, Error> {
+        let mut ret = vec![];
+        for (i, track) in tracks.iter().enumerate() {
+            if self.matches(query, track)? {
+                ret.push(i);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn eval<T: Track>(
+        &self,
+        query: &Query,
+        track: &T,
+        env: &mut Environment,
+    ) -> Result<bool, Error> {
+        Ok(match query {
+            Query::All => true,
+            Query::Text(text) => track
+                .metadata()
+                .values()
+                .flatten()
+                .any(|v| contains_ignore_case(v, text)),
+            Query::Condition(operand, condition) => {
+                let values = self.values(operand, track, env)?;
+                self.test(condition, &values)
+            }
+            Query::Not(q) => !self.eval(q, track, env)?,
+            Query::And(a, b) => self.eval(a, track, env)? && self.eval(b, track, env)?,
+            Query::Or(a, b) => self.eval(a, track, env)? || self.eval(b, track, env)?,
+        })
+    }
+
+    /* every value of a multi-value field is tested, a missing field has no
+     * values */
+    fn values<T: Track>(
+        &self,
+        operand: &Operand,
+        track: &T,
+        env: &mut Environment,
+    ) -> Result<Vec<String>, Error> {
+        let value = match operand {
+            Operand::Field(name) => match track.metadata().get(name) {
+                Some(values) if !values.is_empty() => return Ok(values.clone()),
+                _ => env.get_variable(name),
+            },
+            Operand::Format(exprs) => self.program.evaluate_exprs(env, exprs)?,
+        };
+        Ok(if value.cond { vec![value.val] } else { vec![] })
+    }
+
+    fn test(&self, condition: &Condition, values: &[String]) -> bool {
+        let numbers = |wanted: &str, f: fn(f64, f64) -> bool| match number(wanted) {
+            Some(wanted) => values
+                .iter()
+                .filter_map(|v| number(v))
+                .any(|v| f(v, wanted)),
+            None => false,
+        };
+        let dates = |wanted: &Date, f: fn((i64, i64), (i64, i64)) -> bool| {
+            values
+                .iter()
+                .filter_map(|v| parse_range(v))
+                .any(|v| f(v, wanted.range))
+        };
+        match condition {
+            Condition::Has(s) => values.iter().any(|v| contains_ignore_case(v, s)),
+            Condition::Is(s) => values.iter().any(|v| v.to_lowercase() == s.to_lowercase()),
+            Condition::Equal(n) => numbers(n, |v, n| v == n),
+            Condition::Greater(n) => numbers(n, |v, n| v > n),
+            Condition::Less(n) => numbers(n, |v, n| v < n),
+            Condition::Missing => values.is_empty(),
+            Condition::Present => !values.is_empty(),
+            Condition::Before(d) => dates(d, |v, d| v.1 <= d.0),
+            Condition::After(d) => dates(d, |v, d| v.0 >= d.1),
+            Condition::Since(d) => dates(d, |v, d| v.0 >= d.0),
+            Condition::During(d) => dates(d, |v, d| v.0 >= d.0 && v.1 <= d.1),
+            Condition::DuringLast(n, unit) => {
+                let start = self
+                    .now
+                    .saturating_sub((*n as i64).saturating_mul(unit.seconds()));
+                values
+                    .iter()
+                    .filter_map(|v| parse_range(v))
+                    .any(|(v, _)| v >= start && v <= self.now)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::parse;
+    use std::collections::HashMap;
+
+    fn track(tags: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        tags.iter()
+            .map(|(k, v)| {
+                (
+                    String::from(*k),
+                    v.iter().map(|s| String::from(*s)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn matches(query: &str, track: &HashMap<String, Vec<String>>) -> bool {
+        let mut evaluator = Evaluator::new();
+        /* 2020-01-15 00:00:00 UTC */
+        evaluator.set_now(1_579_046_400);
+        evaluator.matches(&parse(query).unwrap(), track).unwrap()
+    }
+
+    #[test]
+    fn test_strings() {
+        let t = track(&[("artist", &["The Beatles", "Paul"]), ("genre", &["Live"])]);
+        assert!(matches("artist HAS beatles", &t));
+        assert!(matches("artist IS paul", &t));
+        assert!(!matches("artist IS beatles", &t));
+        assert!(matches("artist HAS beatles AND NOT genre IS studio", &t));
+        assert!(!matches("artist HAS beatles AND NOT genre IS live", &t));
+        assert!(matches("beatles", &t));
+        assert!(!matches("stones", &t));
+        assert!(matches("ALL", &t));
+    }
+
+    #[test]
+    fn test_missing_present() {
+        let t = track(&[("artist", &["A"])]);
+        assert!(matches("artist PRESENT", &t));
+        assert!(matches("comment MISSING", &t));
+        assert!(matches("%comment% MISSING", &t));
+        assert!(matches("\"album artist\" PRESENT", &t));
+    }
+
+    #[test]
+    fn test_numbers() {
+        let t = track(&[("rating", &["4"]), ("title", &["abc"])]);
+        assert!(matches("%rating% GREATER 3", &t));
+        assert!(!matches("rating LESS 3", &t));
+        assert!(matches("rating EQUAL 4", &t));
+        assert!(matches("$len(%title%) EQUAL 3", &t));
+        assert!(!matches("title GREATER 3", &t));
+        assert!(!matches("rating GREATER abc", &t));
+    }
+
+    #[test]
+    fn test_dates() {
+        let t = track(&[
+            ("date", &["1999-05-03"]),
+            ("added", &["2020-01-10 08:00:00"]),
+        ]);
+        assert!(matches("date DURING 1999", &t));
+        assert!(matches("date DURING 1999-05", &t));
+        assert!(!matches("date DURING 1999-06", &t));
+        assert!(matches("date AFTER 1998", &t));
+        assert!(!matches("date AFTER 1999", &t));
+        assert!(matches("date BEFORE 2000", &t));
+        assert!(!matches("date BEFORE 1999-05-03", &t));
+        assert!(matches("date SINCE 1999-05-03", &t));
+        assert!(!matches("date SINCE 1999-05-04", &t));
+        assert!(matches("added DURING LAST 1 WEEK", &t));
+        assert!(!matches("added DURING LAST 4 DAYS", &t));
+        assert!(matches("added DURING LAST 120 HOURS", &t));
+        assert!(!matches("title DURING LAST 1 WEEK", &t));
+        assert!(matches("$year(%date%) AFTER 1998", &t));
+    }
+
+    #[test]
+    fn test_error() {
+        let t = track(&[]);
+        let query = parse("$add() PRESENT").unwrap();
+        assert!(Evaluator::new().matches(&query, &t).is_err());
+    }
+}
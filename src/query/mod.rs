@@ -0,0 +1,112 @@
+/* foobar2000 queries, e.g. `artist HAS beatles AND NOT genre IS live`
+ *
+ * based on https://wiki.hydrogenaud.io/index.php?title=Foobar2000:Query_syntax */
+
+mod date;
+mod eval;
+mod parser;
+
+pub use self::eval::Evaluator;
+pub use self::parser::parse;
+
+use crate::types::Expr;
+
+/// A parsed query
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// `ALL`, matches every track
+    All,
+    /// Search text without an operator, matches tracks with the text in any
+    /// field
+    Text(String),
+    /// `<operand> <condition>`, e.g. `artist HAS beatles`
+    Condition(Operand, Condition),
+    /// `NOT <query>`
+    Not(Box<Query>),
+    /// `<query> AND <query>`, also two queries next to each other
+    And(Box<Query>, Box<Query>),
+    /// `<query> OR <query>`
+    Or(Box<Query>, Box<Query>),
+}
+
+/// What a condition is tested against
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    /// A plain field name, e.g. `artist` or `"album artist"`
+    Field(String),
+    /// A titleformat expression, e.g. `%rating%` or `$year(%date%)`
+    Format(Vec<Expr>),
+}
+
+/// The test a [`Query::Condition`] applies to its operand
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    /// `HAS <text>`: contains the text, ignoring case
+    Has(String),
+    /// `IS <text>`: equals the text, ignoring case
+    Is(String),
+    /// `EQUAL <number>`
+    Equal(String),
+    /// `GREATER <number>`
+    Greater(String),
+    /// `LESS <number>`
+    Less(String),
+    /// `MISSING`: the field is not set
+    Missing,
+    /// `PRESENT`: the field is set
+    Present,
+    /// `BEFORE <date>`: entirely before the date
+    Before(Date),
+    /// `AFTER <date>`: entirely after the date
+    After(Date),
+    /// `SINCE <date>`: on or after the date
+    Since(Date),
+    /// `DURING <date>`: within the date, e.g. within the year for `1999`
+    During(Date),
+    /// `DURING LAST <n> <unit>`: within the given time before now
+    DuringLast(u32, TimeUnit),
+}
+
+/// The date of `BEFORE`, `AFTER`, `SINCE` and `DURING`, parsed once when
+/// the query is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Date {
+    /// The date as written in the query, e.g. `1999-05`
+    pub text: String,
+    /// The first second of the date and the first second after it, in
+    /// seconds since the Unix epoch
+    pub range: (i64, i64),
+}
+
+impl Date {
+    /* None if s is not an ISO 8601 date */
+    pub(crate) fn parse(s: &str) -> Option<Date> {
+        Some(Date {
+            text: String::from(s),
+            range: date::parse_range(s)?,
+        })
+    }
+}
+
+/// The unit of `DURING LAST <n> <unit>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+}
+
+impl TimeUnit {
+    /// The length of the unit in seconds
+    pub fn seconds(self) -> i64 {
+        match self {
+            TimeUnit::Seconds => 1,
+            TimeUnit::Minutes => 60,
+            TimeUnit::Hours => 60 * 60,
+            TimeUnit::Days => 24 * 60 * 60,
+            TimeUnit::Weeks => 7 * 24 * 60 * 60,
+        }
+    }
+}
@@ -0,0 +1,536 @@
+use crate::parser;
+use crate::query::{Condition, Date, Operand, Query, TimeUnit};
+use crate::types::Error;
+use crate::types::Error::*;
+
+/* the query language is a sequence of whitespace separated words where the
+ * operands can be titleformat expressions (which may contain whitespace) so
+ * it is split into tokens first and then parsed by recursive descent */
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Word(&'a str),
+    Quoted(&'a str),
+}
+
+/* a token and its byte offset in the query */
+type Spanned<'a> = (Token<'a>, usize);
+
+fn error(pos: usize, message: &str) -> Error {
+    QueryParseError(pos, String::from(message))
+}
+
+/* a word ends at whitespace or an unmatched ')', but not within %field% or
+ * the arguments of $func(...) */
+fn word_end(input: &str, start: usize) -> Result<usize, Error> {
+    let mut in_field = false;
+    let mut depth = 0;
+    for (i, c) in input[start..].char_indices() {
+        match c {
+            '%' => in_field = !in_field,
+            _ if in_field => {}
+            '(' => depth += 1,
+            ')' if depth == 0 => return Ok(start + i),
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => return Ok(start + i),
+            _ => {}
+        }
+    }
+    if in_field {
+        Err(error(start, "unterminated %field%"))
+    } else if depth > 0 {
+        Err(error(start, "unbalanced parenthesis"))
+    } else {
+        Ok(input.len())
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Spanned<'_>>, Error> {
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        match c {
+            c if c.is_whitespace() => pos += c.len_utf8(),
+            '(' => {
+                tokens.push((Token::Open, pos));
+                pos += 1;
+            }
+            ')' => {
+                tokens.push((Token::Close, pos));
+                pos += 1;
+            }
+            '"' => {
+                let len = input[pos + 1..]
+                    .find('"')
+                    .ok_or_else(|| error(pos, "unterminated quote"))?;
+                tokens.push((Token::Quoted(&input[pos + 1..pos + 1 + len]), pos));
+                pos += len + 2;
+            }
+            _ => {
+                let end = word_end(input, pos)?;
+                tokens.push((Token::Word(&input[pos..end]), pos));
+                pos = end;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+const CONDITIONS: [&str; 11] = [
+    "HAS", "IS", "EQUAL", "GREATER", "LESS", "MISSING", "PRESENT", "BEFORE", "AFTER", "SINCE",
+    "DURING",
+];
+
+fn time_unit(word: &str) -> Option<TimeUnit> {
+    match word {
+        "SECOND" | "SECONDS" => Some(TimeUnit::Seconds),
+        "MINUTE" | "MINUTES" => Some(TimeUnit::Minutes),
+        "HOUR" | "HOURS" => Some(TimeUnit::Hours),
+        "DAY" | "DAYS" => Some(TimeUnit::Days),
+        "WEEK" | "WEEKS" => Some(TimeUnit::Weeks),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Spanned<'a>>,
+    next: usize,
+    /* the offset reported for errors at the end of the query */
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(_, pos)| *pos)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.peek() == Some(&Token::Word(keyword))
+    }
+
+    fn or(&mut self) -> Result<Query, Error> {
+        let mut lhs = self.and()?;
+        while self.is_keyword("OR") {
+            self.next += 1;
+            let rhs = self.and()?;
+            lhs = Query::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    /* two queries next to each other must both match */
+    fn and(&mut self) -> Result<Query, Error> {
+        let mut lhs = self.not()?;
+        loop {
+            if self.is_keyword("AND") {
+                self.next += 1;
+            } else if self.peek().is_none()
+                || self.peek() == Some(&Token::Close)
+                || self.is_keyword("OR")
+            {
+                return Ok(lhs);
+            }
+            let rhs = self.not()?;
+            lhs = Query::And(Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn not(&mut self) -> Result<Query, Error> {
+        if self.is_keyword("NOT") {
+            self.next += 1;
+            Ok(Query::Not(Box::new(self.not()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Query, Error> {
+        let pos = self.pos();
+        match self.peek() {
+            None => Err(error(pos, "expected a query")),
+            Some(Token::Open) => {
+                self.next += 1;
+                let query = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(error(self.pos(), "expected )"));
+                }
+                self.next += 1;
+                Ok(query)
+            }
+            Some(Token::Close) => Err(error(pos, "unexpected )")),
+            Some(Token::Word("ALL")) => {
+                self.next += 1;
+                Ok(Query::All)
+            }
+            Some(Token::Word(w)) if CONDITIONS.contains(w) => {
+                Err(error(pos, &format!("expected a field before {}", w)))
+            }
+            Some(Token::Word(w)) if ["AND", "OR"].contains(w) => {
+                Err(error(pos, &format!("unexpected {}", w)))
+            }
+            Some(Token::Word(w)) => {
+                let text = *w;
+                self.next += 1;
+                if text.contains('%') || text.contains('$') {
+                    let exprs =
+                        parser::parse(text).map_err(|_| error(pos, "invalid titleformat"))?;
+                    let condition = self
+                        .condition()?
+                        .ok_or_else(|| error(self.pos(), "expected a condition"))?;
+                    Ok(Query::Condition(Operand::Format(exprs), condition))
+                } else {
+                    self.field_or_text(text)
+                }
+            }
+            Some(Token::Quoted(q)) => {
+                let text = *q;
+                self.next += 1;
+                self.field_or_text(text)
+            }
+        }
+    }
+
+    /* a word is a field name if a condition follows it and search text
+     * otherwise */
+    fn field_or_text(&mut self, text: &str) -> Result<Query, Error> {
+        Ok(match self.condition()? {
+            Some(condition) => Query::Condition(Operand::Field(String::from(text)), condition),
+            None => Query::Text(String::from(text)),
+        })
+    }
+
+    fn condition(&mut self) -> Result<Option<Condition>, Error> {
+        let keyword = match self.peek() {
+            Some(Token::Word(w)) if CONDITIONS.contains(w) => *w,
+            _ => return Ok(None),
+        };
+        self.next += 1;
+        Ok(Some(match keyword {
+            "MISSING" => Condition::Missing,
+            "PRESENT" => Condition::Present,
+            "HAS" => Condition::Has(self.value(keyword)?),
+            "IS" => Condition::Is(self.value(keyword)?),
+            "EQUAL" => Condition::Equal(self.value(keyword)?),
+            "GREATER" => Condition::Greater(self.value(keyword)?),
+            "LESS" => Condition::Less(self.value(keyword)?),
+            "DURING" if self.is_keyword("LAST") => {
+                self.next += 1;
+                self.during_last()?
+            }
+            "BEFORE" => Condition::Before(self.date(keyword)?),
+            "AFTER" => Condition::After(self.date(keyword)?),
+            "SINCE" => Condition::Since(self.date(keyword)?),
+            _ => Condition::During(self.date(keyword)?),
+        }))
+    }
+
+    /* the dates are parsed here so a typo is reported instead of silently
+     * never matching */
+    fn date(&mut self, keyword: &str) -> Result<Date, Error> {
+        let pos = self.pos();
+        let value = self.value(keyword)?;
+        Date::parse(&value).ok_or_else(|| error(pos, &format!("invalid date {}", value)))
+    }
+
+    /* DURING LAST <n> <unit> */
+    fn during_last(&mut self) -> Result<Condition, Error> {
+        let pos = self.pos();
+        let n = match self.peek() {
+            Some(Token::Word(w)) => w.parse::<u32>().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| error(pos, "expected a number after DURING LAST"))?;
+        self.next += 1;
+        let pos = self.pos();
+        let unit = match self.peek() {
+            Some(Token::Word(w)) => time_unit(w),
+            _ => None,
+        }
+        .ok_or_else(|| error(pos, "expected SECONDS, MINUTES, HOURS, DAYS or WEEKS"))?;
+        self.next += 1;
+        Ok(Condition::DuringLast(n, unit))
+    }
+
+    /* either a quoted string or all the words up to the next AND, OR, NOT,
+     * parenthesis or the field of the next condition */
+    fn value(&mut self, keyword: &str) -> Result<String, Error> {
+        if let Some(Token::Quoted(q)) = self.peek() {
+            let value = String::from(*q);
+            self.next += 1;
+            return Ok(value);
+        }
+        let mut words = vec![];
+        while let Some(Token::Word(w)) = self.peek() {
+            if ["AND", "OR", "NOT"].contains(w) {
+                break;
+            }
+            let starts_condition = matches!(
+                self.tokens.get(self.next + 1),
+                Some((Token::Word(next), _)) if CONDITIONS.contains(next)
+            );
+            if starts_condition && !words.is_empty() {
+                break;
+            }
+            words.push(*w);
+            self.next += 1;
+        }
+        if words.is_empty() {
+            return Err(error(
+                self.pos(),
+                &format!("expected a value after {}", keyword),
+            ));
+        }
+        Ok(words.join(" "))
+    }
+}
+
+/// Parses a foobar2000 query
+///
+/// Operands are either plain field names, quoted if they contain spaces, or
+/// titleformat expressions.  Words without an operator are search text and
+/// queries next to each other must all match.  An unquoted value runs up to
+/// the next `AND`, `OR`, `NOT`, parenthesis or operand followed by an
+/// operator.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::query::{self, Condition, Operand, Query};
+/// let query = query::parse("artist HAS beatles AND NOT genre IS live").unwrap();
+/// assert_eq!(
+///     query,
+///     Query::And(
+///         Box::new(Query::Condition(
+///             Operand::Field("artist".into()),
+///             Condition::Has("beatles".into())
+///         )),
+///         Box::new(Query::Not(Box::new(Query::Condition(
+///             Operand::Field("genre".into()),
+///             Condition::Is("live".into())
+///         )))),
+///     )
+/// );
+/// assert!(query::parse("artist HAS").is_err());
+/// ```
+pub fn parse(input: &str) -> Result<Query, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        next: 0,
+        end: input.len(),
+    };
+    if parser.tokens.is_empty() {
+        return Err(error(0, "empty query"));
+    }
+    let query = parser.or()?;
+    match parser.peek() {
+        None => Ok(query),
+        _ => Err(error(parser.pos(), "unexpected )")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Expr::*;
+
+    fn field(name: &str, condition: Condition) -> Query {
+        Query::Condition(Operand::Field(String::from(name)), condition)
+    }
+
+    fn date(s: &str) -> Date {
+        Date::parse(s).unwrap()
+    }
+
+    fn and(a: Query, b: Query) -> Query {
+        Query::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Query, b: Query) -> Query {
+        Query::Or(Box::new(a), Box::new(b))
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("(%album artist% HAS \"a b\") $if(%a%, b)").unwrap(),
+            vec![
+                (Token::Open, 0),
+                (Token::Word("%album artist%"), 1),
+                (Token::Word("HAS"), 16),
+                (Token::Quoted("a b"), 20),
+                (Token::Close, 25),
+                (Token::Word("$if(%a%, b)"), 27),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(
+            parse("title HAS let it be").unwrap(),
+            field("title", Condition::Has(String::from("let it be")))
+        );
+        assert_eq!(
+            parse("\"album artist\" IS \"The Beatles\"").unwrap(),
+            field("album artist", Condition::Is(String::from("The Beatles")))
+        );
+        assert_eq!(
+            parse("comment MISSING").unwrap(),
+            field("comment", Condition::Missing)
+        );
+        assert_eq!(
+            parse("comment PRESENT").unwrap(),
+            field("comment", Condition::Present)
+        );
+        assert_eq!(
+            parse("%rating% GREATER 3").unwrap(),
+            Query::Condition(
+                Operand::Format(vec![Variable(String::from("rating"))]),
+                Condition::Greater(String::from("3"))
+            )
+        );
+        assert_eq!(
+            parse("$len(%title%) LESS 5").unwrap(),
+            Query::Condition(
+                Operand::Format(vec![FuncCall(
+                    String::from("len"),
+                    vec![vec![Variable(String::from("title"))]]
+                )]),
+                Condition::Less(String::from("5"))
+            )
+        );
+        assert_eq!(
+            parse("artist HAS beatles genre IS live").unwrap(),
+            and(
+                field("artist", Condition::Has(String::from("beatles"))),
+                field("genre", Condition::Is(String::from("live")))
+            )
+        );
+        assert_eq!(
+            parse("title HAS let it be %rating% GREATER 3").unwrap(),
+            and(
+                field("title", Condition::Has(String::from("let it be"))),
+                Query::Condition(
+                    Operand::Format(vec![Variable(String::from("rating"))]),
+                    Condition::Greater(String::from("3"))
+                )
+            )
+        );
+        assert_eq!(
+            parse("tracknumber EQUAL 1").unwrap(),
+            field("tracknumber", Condition::Equal(String::from("1")))
+        );
+    }
+
+    #[test]
+    fn test_date_conditions() {
+        assert_eq!(
+            parse("date AFTER 1999").unwrap(),
+            field("date", Condition::After(date("1999")))
+        );
+        assert_eq!(
+            parse("added SINCE 2020-01-02 10:00:00").unwrap(),
+            field("added", Condition::Since(date("2020-01-02 10:00:00")))
+        );
+        assert_eq!(
+            parse("date BEFORE 1999-05 OR date DURING 2001").unwrap(),
+            or(
+                field("date", Condition::Before(date("1999-05"))),
+                field("date", Condition::During(date("2001")))
+            )
+        );
+        assert_eq!(
+            parse("%added% DURING LAST 2 WEEKS").unwrap(),
+            Query::Condition(
+                Operand::Format(vec![Variable(String::from("added"))]),
+                Condition::DuringLast(2, TimeUnit::Weeks)
+            )
+        );
+        assert_eq!(
+            parse("added DURING LAST 1 DAY").unwrap(),
+            field("added", Condition::DuringLast(1, TimeUnit::Days))
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let a = || field("a", Condition::Present);
+        let b = || field("b", Condition::Present);
+        let c = || field("c", Condition::Present);
+        assert_eq!(
+            parse("a PRESENT OR b PRESENT AND c PRESENT").unwrap(),
+            or(a(), and(b(), c()))
+        );
+        assert_eq!(
+            parse("(a PRESENT OR b PRESENT) c PRESENT").unwrap(),
+            and(or(a(), b()), c())
+        );
+        assert_eq!(
+            parse("NOT a PRESENT AND b PRESENT").unwrap(),
+            and(Query::Not(Box::new(a())), b())
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            parse("yellow submarine").unwrap(),
+            and(
+                Query::Text(String::from("yellow")),
+                Query::Text(String::from("submarine"))
+            )
+        );
+        assert_eq!(parse("ALL").unwrap(), Query::All);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse("").unwrap_err(), error(0, "empty query"));
+        assert_eq!(
+            parse("artist HAS").unwrap_err(),
+            error(10, "expected a value after HAS")
+        );
+        assert_eq!(
+            parse("artist HAS AND x").unwrap_err(),
+            error(11, "expected a value after HAS")
+        );
+        assert_eq!(
+            parse("HAS x").unwrap_err(),
+            error(0, "expected a field before HAS")
+        );
+        assert_eq!(parse("(a").unwrap_err(), error(2, "expected )"));
+        assert_eq!(parse("a)").unwrap_err(), error(1, "unexpected )"));
+        assert_eq!(parse("a OR").unwrap_err(), error(4, "expected a query"));
+        assert_eq!(parse("\"a").unwrap_err(), error(0, "unterminated quote"));
+        assert_eq!(
+            parse("x %a HAS b").unwrap_err(),
+            error(2, "unterminated %field%")
+        );
+        assert_eq!(
+            parse("$if(a HAS b").unwrap_err(),
+            error(0, "unbalanced parenthesis")
+        );
+        assert_eq!(
+            parse("%a% b").unwrap_err(),
+            error(4, "expected a condition")
+        );
+        assert_eq!(
+            parse("date AFTER soon").unwrap_err(),
+            error(11, "invalid date soon")
+        );
+        assert_eq!(
+            parse("date DURING LAST WEEK").unwrap_err(),
+            error(17, "expected a number after DURING LAST")
+        );
+        assert_eq!(
+            parse("date DURING LAST 2 YEARS").unwrap_err(),
+            error(19, "expected SECONDS, MINUTES, HOURS, DAYS or WEEKS")
+        );
+    }
+}
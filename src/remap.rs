@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::environment::{value_string, Value};
+
+/* the foobar2000 fallbacks for fields that are commonly missing */
+fn default_chain(field: &str) -> Option<&'static [&'static str]> {
+    match field {
+        "album artist" => Some(&["artist", "composer", "performer"]),
+        "artist" => Some(&["album artist", "composer", "performer"]),
+        _ => None,
+    }
+}
+
+/// A table of fallback fields consulted when a field is looked up with
+/// `%field%`.  If the field itself is missing, the fields in its chain are
+/// tried in order and the first that exists is used.
+///
+/// The default table mirrors foobar2000:
+///
+/// * `%album artist%` falls back to `%artist%`, `%composer%` and `%performer%`
+/// * `%artist%` falls back to `%album artist%`, `%composer%` and `%performer%`
+/// * `%track artist%` is `%artist%` when it differs from `%album artist%` and
+///   empty otherwise
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::remap::Remapping;
+/// # use titleformat_rs::program::Program;
+/// # use std::collections::HashMap;
+/// let mut remap = Remapping::new();
+/// remap.alias("year", &["date", "originaldate"]);
+/// let mut program = Program::new();
+/// program.set_remapping(remap);
+/// program.parse("%year%").unwrap();
+/// let mut metadata = HashMap::new();
+/// metadata.insert("originaldate".into(), vec!["1999".into()]);
+/// assert_eq!(program.run_with_meta(metadata).unwrap(), "1999");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Remapping {
+    defaults: bool,
+    /* user defined chains, consulted before the defaults */
+    chains: HashMap<String, Vec<String>>,
+}
+
+impl Default for Remapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Remapping {
+    /// Constructs a new `Remapping` with the foobar2000 defaults
+    pub fn new() -> Self {
+        Remapping {
+            defaults: true,
+            chains: HashMap::new(),
+        }
+    }
+
+    /// Constructs a new `Remapping` without any fallbacks
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use titleformat_rs::remap::Remapping;
+    /// # use titleformat_rs::program::Program;
+    /// # use std::collections::HashMap;
+    /// let mut program = Program::new();
+    /// program.set_remapping(Remapping::empty());
+    /// program.parse("[%album artist%]").unwrap();
+    /// let mut metadata = HashMap::new();
+    /// metadata.insert("artist".into(), vec!["Happy".into()]);
+    /// assert_eq!(program.run_with_meta(metadata).unwrap(), "");
+    /// ```
+    pub fn empty() -> Self {
+        Remapping {
+            defaults: false,
+            chains: HashMap::new(),
+        }
+    }
+
+    /// Makes `field` fall back to the fields in `chain`, in order, replacing
+    /// any previous chain for `field`.  The fields in the chain are not
+    /// remapped themselves.
+    pub fn alias(&mut self, field: &str, chain: &[&str]) {
+        self.chains.insert(
+            String::from(field),
+            chain.iter().map(|f| String::from(*f)).collect(),
+        );
+    }
+
+    /// Removes the chain for `field` so that it is only read from the tags.
+    /// Also removes any default chain for `field`.
+    pub fn remove(&mut self, field: &str) {
+        self.chains.insert(String::from(field), vec![]);
+    }
+
+    fn first_of<'f, I, F>(field: &str, chain: I, get: &F) -> Value
+    where
+        I: IntoIterator<Item = &'f str>,
+        F: Fn(&str) -> Value,
+    {
+        let val = get(field);
+        if val.cond {
+            return val;
+        }
+        chain.into_iter().map(get).find(|v| v.cond).unwrap_or(val)
+    }
+
+    /* looks up field through its chain, with get reading a single tag.
+     * Returns None if field is not remapped */
+    pub(crate) fn lookup<F>(&self, field: &str, get: &F) -> Option<Value>
+    where
+        F: Fn(&str) -> Value,
+    {
+        if let Some(chain) = self.chains.get(field) {
+            return Some(Self::first_of(field, chain.iter().map(|f| f.as_str()), get));
+        }
+        if !self.defaults {
+            return None;
+        }
+        if field == "track artist" {
+            let artist = self.lookup("artist", get)?;
+            let album_artist = self.lookup("album artist", get)?;
+            return Some(if artist.val == album_artist.val {
+                value_string("", false)
+            } else {
+                artist
+            });
+        }
+        default_chain(field).map(|chain| Self::first_of(field, chain.iter().copied(), get))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn getter<'t>(tags: &'t [(&'t str, &'t str)]) -> impl Fn(&str) -> Value + 't {
+        move |key: &str| match tags.iter().find(|(k, _)| *k == key) {
+            Some((_, v)) => value_string(v, true),
+            None => value_string("?", false),
+        }
+    }
+
+    #[test]
+    fn test_album_artist() {
+        let remap = Remapping::new();
+        let get = getter(&[("composer", "C")]);
+        assert_eq!(
+            remap.lookup("album artist", &get),
+            Some(value_string("C", true))
+        );
+        let get = getter(&[("composer", "C"), ("artist", "A")]);
+        assert_eq!(
+            remap.lookup("album artist", &get),
+            Some(value_string("A", true))
+        );
+        let get = getter(&[]);
+        assert_eq!(
+            remap.lookup("album artist", &get),
+            Some(value_string("?", false))
+        );
+    }
+
+    #[test]
+    fn test_track_artist() {
+        let remap = Remapping::new();
+        let get = getter(&[("artist", "A")]);
+        assert_eq!(
+            remap.lookup("track artist", &get),
+            Some(value_string("", false))
+        );
+        let get = getter(&[("artist", "A"), ("album artist", "Various")]);
+        assert_eq!(
+            remap.lookup("track artist", &get),
+            Some(value_string("A", true))
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut remap = Remapping::new();
+        remap.alias("year", &["date", "originaldate"]);
+        let get = getter(&[("originaldate", "1999")]);
+        assert_eq!(remap.lookup("year", &get), Some(value_string("1999", true)));
+        assert_eq!(remap.lookup("album", &get), None);
+
+        remap.remove("artist");
+        let get = getter(&[("album artist", "B")]);
+        assert_eq!(remap.lookup("artist", &get), Some(value_string("?", false)));
+    }
+
+    #[test]
+    fn test_empty() {
+        let remap = Remapping::empty();
+        let get = getter(&[("artist", "A")]);
+        assert_eq!(remap.lookup("album artist", &get), None);
+        assert_eq!(remap.lookup("track artist", &get), None);
+    }
+}
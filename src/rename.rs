@@ -0,0 +1,540 @@
+use crate::path::PathMode;
+use crate::program::Program;
+use crate::track::Track;
+use crate::types::Error;
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::path::{Component, Path, PathBuf};
+
+/// A file to rename
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Move {
+    /// The index of the file in the list the plan was made from
+    pub index: usize,
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Why a plan cannot be executed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    /// Several files would get the same name, the indices of the files
+    Duplicate(PathBuf, Vec<usize>),
+    /// A file that is not renamed already has the name
+    Exists(PathBuf, usize),
+    /// The name would put the file outside of the root directory
+    OutsideRoot(PathBuf, usize),
+}
+
+/// The renames needed to give each file the name built from its metadata
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// The files that get a new name, files that keep theirs are left out
+    pub moves: Vec<Move>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Plans renaming files to names built by a titleformat program from their
+/// metadata, e.g. `%album artist%/%album%/%tracknumber% - %title%`.  The
+/// extension of each file is kept and the names are sanitized with a
+/// [`PathMode`].
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::rename::Planner;
+/// # use std::collections::HashMap;
+/// # use std::path::PathBuf;
+/// let mut metadata = HashMap::new();
+/// metadata.insert("artist".to_string(), vec!["AC/DC".to_string()]);
+/// metadata.insert("title".to_string(), vec!["Thunderstruck".to_string()]);
+/// let files = vec![(PathBuf::from("/music/in/01.flac"), metadata)];
+/// let planner = Planner::parse("%artist%/%title%", "/music").unwrap();
+/// let plan = planner.plan(&files).unwrap();
+/// assert!(plan.conflicts.is_empty());
+/// assert_eq!(plan.moves[0].to, PathBuf::from("/music/AC_DC/Thunderstruck.flac"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Planner {
+    program: Program,
+    mode: PathMode,
+    root: PathBuf,
+    case_insensitive: bool,
+}
+
+/* whether path names a file under root, without any . or .. in the way */
+fn is_under(path: &Path, root: &Path) -> bool {
+    match path.strip_prefix(root) {
+        Ok(rest) => {
+            rest.components().next().is_some()
+                && rest.components().all(|c| matches!(c, Component::Normal(_)))
+        }
+        Err(_) => false,
+    }
+}
+
+/* the name used to compare targets */
+fn key(path: &Path, case_insensitive: bool) -> String {
+    let s = path.to_string_lossy();
+    if case_insensitive {
+        s.to_lowercase()
+    } else {
+        s.into_owned()
+    }
+}
+
+impl Planner {
+    /// Constructs a new `Planner` putting the files under `root`.  The names
+    /// are sanitized for and compared the way the current platform does.
+    pub fn new<P: AsRef<Path>>(program: Program, root: P) -> Self {
+        Planner {
+            program,
+            mode: PathMode::native(),
+            root: root.as_ref().to_path_buf(),
+            case_insensitive: cfg!(windows) || cfg!(target_os = "macos"),
+        }
+    }
+
+    /// Parses `pattern` into a new `Planner`
+    pub fn parse<P: AsRef<Path>>(pattern: &str, root: P) -> Result<Self, Error> {
+        let mut program = Program::new();
+        program.parse(pattern)?;
+        Ok(Planner::new(program, root))
+    }
+
+    /// Sets how the names are sanitized
+    pub fn set_path_mode(&mut self, mode: PathMode) {
+        self.mode = mode;
+    }
+
+    /// Sets whether names differing only in case are the same file, as on
+    /// FAT, NTFS or APFS
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    /// Makes the plan for renaming `files`, pairs of the path of a file and
+    /// its metadata.  The file system is only read to find the existing
+    /// files the new names would collide with.
+    pub fn plan<T: Track>(&self, files: &[(PathBuf, T)]) -> Result<Plan, Error> {
+        let mut plan = Plan::default();
+        let mut targets: Vec<(String, PathBuf, Vec<usize>)> = vec![];
+        let mut by_key: HashMap<String, usize> = HashMap::new();
+
+        /* the limits are enforced once the extension is added */
+        let mut unlimited = self.mode.clone();
+        unlimited.set_component_limit(None);
+        unlimited.set_path_limit(None);
+        /* the root and the separator after it count towards the path limit */
+        let root = self.root.to_string_lossy();
+        let prefix = root.chars().count() + usize::from(!root.ends_with(['/', '\\']));
+
+        for (index, (from, track)) in files.iter().enumerate() {
+            let mut name = self.program.run_path(track, &unlimited)?;
+            if let Some(ext) = from.extension() {
+                name.push('.');
+                name.push_str(&ext.to_string_lossy());
+            }
+            let to = self.root.join(self.mode.finish_under(&name, prefix));
+            if !is_under(&to, &self.root) {
+                plan.conflicts.push(Conflict::OutsideRoot(to, index));
+                continue;
+            }
+            let k = key(&to, self.case_insensitive);
+            match by_key.get(&k) {
+                Some(&i) => targets[i].2.push(index),
+                None => {
+                    by_key.insert(k.clone(), targets.len());
+                    targets.push((k, to.clone(), vec![index]));
+                }
+            }
+            /* a case-only change on a case-insensitive file system is
+             * still a rename */
+            if from != &to {
+                plan.moves.push(Move {
+                    index,
+                    from: from.clone(),
+                    to,
+                });
+            }
+        }
+
+        /* the files moving away free their names */
+        let sources: HashMap<String, usize> = files
+            .iter()
+            .enumerate()
+            .map(|(i, (from, _))| (key(from, self.case_insensitive), i))
+            .collect();
+        for (k, to, indices) in targets {
+            if indices.len() > 1 {
+                plan.conflicts
+                    .push(Conflict::Duplicate(to.clone(), indices.clone()));
+            }
+            let moving = plan.moves.iter().any(|m| m.index == indices[0]);
+            if moving && !sources.contains_key(&k) && self.exists(&to) {
+                plan.conflicts.push(Conflict::Exists(to, indices[0]));
+            }
+        }
+        Ok(plan)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if path.exists() {
+            return true;
+        }
+        if !self.case_insensitive {
+            return false;
+        }
+        /* the file system itself may be case-sensitive */
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        let name = name.to_string_lossy().to_lowercase();
+        match fs::read_dir(parent) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .any(|e| e.file_name().to_string_lossy().to_lowercase() == name),
+            Err(_) => false,
+        }
+    }
+}
+
+/* journal lines are "from\tto" with \, tab and newline escaped */
+fn escape(path: &Path) -> io::Result<String> {
+    let s = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "path is not valid UTF-8"))?;
+    Ok(s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n"))
+}
+
+fn unescape(s: &str) -> PathBuf {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('t') => ret.push('\t'),
+                Some('n') => ret.push('\n'),
+                Some(c) => ret.push(c),
+                None => ret.push('\\'),
+            },
+            (c, false) => ret.push(c),
+        }
+    }
+    PathBuf::from(ret)
+}
+
+/* renames the files in two steps, first to a temporary name next to each
+ * file and then to the final name, so that swapping names and case-only
+ * changes work.  Everything done is undone on failure. */
+fn apply(moves: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let mut done: Vec<(PathBuf, PathBuf)> = vec![];
+    let result = (|| {
+        let mut temps = vec![];
+        for (i, (from, _)) in moves.iter().enumerate() {
+            let name = from
+                .file_name()
+                .map_or_else(Default::default, |n| n.to_string_lossy().into_owned());
+            let temp =
+                from.with_file_name(format!(".{}.{}-{}.rename", name, std::process::id(), i));
+            fs::rename(from, &temp)?;
+            done.push((from.clone(), temp.clone()));
+            temps.push(temp);
+        }
+        for (temp, (_, to)) in temps.iter().zip(moves) {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(temp, to)?;
+            done.push((temp.clone(), to.clone()));
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        for (from, to) in done.iter().rev() {
+            let _ = fs::rename(to, from);
+        }
+    }
+    result
+}
+
+impl Plan {
+    /// Returns whether the plan can be executed
+    pub fn is_ok(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Describes what executing the plan would do, one line per rename
+    /// followed by the conflicts
+    pub fn report(&self) -> String {
+        let mut ret = String::new();
+        for m in &self.moves {
+            let _ = writeln!(ret, "{} -> {}", m.from.display(), m.to.display());
+        }
+        for conflict in &self.conflicts {
+            let _ = match conflict {
+                Conflict::Duplicate(to, indices) => writeln!(
+                    ret,
+                    "conflict: {} files would be named {}",
+                    indices.len(),
+                    to.display()
+                ),
+                Conflict::Exists(to, _) => {
+                    writeln!(ret, "conflict: {} already exists", to.display())
+                }
+                Conflict::OutsideRoot(to, _) => {
+                    writeln!(ret, "conflict: {} is outside the root", to.display())
+                }
+            };
+        }
+        ret
+    }
+
+    /// Renames the files, creating the directories needed, and writes an
+    /// undo journal to `journal` for [`undo`].  Nothing is renamed if the
+    /// plan has conflicts, and if one rename fails the files already
+    /// renamed are given their old names back and the journal is removed.
+    /// Directories created stay.
+    pub fn execute<P: AsRef<Path>>(&self, journal: P) -> io::Result<()> {
+        if !self.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                self.report().trim_end().to_string(),
+            ));
+        }
+        let mut lines = String::new();
+        for m in &self.moves {
+            lines.push_str(&format!("{}\t{}\n", escape(&m.from)?, escape(&m.to)?));
+        }
+        /* the journal is written first so a crash can be recovered from */
+        let journal = journal.as_ref();
+        let mut file = fs::File::create(journal)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_all()?;
+        let moves: Vec<(PathBuf, PathBuf)> = self
+            .moves
+            .iter()
+            .map(|m| (m.from.clone(), m.to.clone()))
+            .collect();
+        /* everything was rolled back, there is nothing left to undo */
+        let result = apply(&moves);
+        if result.is_err() {
+            let _ = fs::remove_file(journal);
+        }
+        result
+    }
+}
+
+/// Gives the files renamed by [`Plan::execute`] their old names back using
+/// its `journal`
+pub fn undo<P: AsRef<Path>>(journal: P) -> io::Result<()> {
+    let mut moves = vec![];
+    for line in BufReader::new(fs::File::open(journal)?).lines() {
+        let line = line?;
+        let (from, to) = line
+            .split_once('\t')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid journal line"))?;
+        moves.push((unescape(to), unescape(from)));
+    }
+    /* names freed by the files moving back are fine to take */
+    for (_, to) in &moves {
+        if to.exists() && !moves.iter().any(|(from, _)| from == to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+    }
+    apply(&moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str) -> HashMap<String, Vec<String>> {
+        HashMap::from([(String::from("title"), vec![String::from(title)])])
+    }
+
+    /* a fresh directory for each test */
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "titleformat-rename-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_plan() {
+        let files = vec![
+            (PathBuf::from("/in/1.mp3"), track("a")),
+            (PathBuf::from("/out/b.flac"), track("b")),
+        ];
+        let plan = Planner::parse("%title%", "/out")
+            .unwrap()
+            .plan(&files)
+            .unwrap();
+        assert!(plan.is_ok());
+        assert_eq!(
+            plan.moves,
+            vec![Move {
+                index: 0,
+                from: PathBuf::from("/in/1.mp3"),
+                to: PathBuf::from("/out/a.mp3")
+            }]
+        );
+        assert_eq!(plan.report(), "/in/1.mp3 -> /out/a.mp3\n");
+
+        let mut planner = Planner::parse("%title%", "/out").unwrap();
+        let mut mode = PathMode::linux();
+        mode.set_component_limit(Some(6));
+        planner.set_path_mode(mode);
+        let files = vec![(PathBuf::from("/in/1.mp3"), track("abcdef"))];
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(plan.moves[0].to, PathBuf::from("/out/ab.mp3"));
+    }
+
+    #[test]
+    fn test_stays_under_root() {
+        let mut planner = Planner::parse("[%discnumber%]/%album%/%title%", "/music").unwrap();
+        planner.set_path_mode(PathMode::linux());
+        let mut metadata = track("t");
+        metadata.insert(String::from("album"), vec![String::from("..")]);
+        let files = vec![(PathBuf::from("/in/1.flac"), metadata)];
+        let plan = planner.plan(&files).unwrap();
+        assert!(plan.is_ok(), "{}", plan.report());
+        assert_eq!(plan.moves[0].to, PathBuf::from("/music/__/t.flac"));
+
+        assert!(is_under(Path::new("/music/a/b"), Path::new("/music")));
+        assert!(!is_under(Path::new("/music/../b"), Path::new("/music")));
+        assert!(!is_under(Path::new("/b"), Path::new("/music")));
+        assert!(!is_under(Path::new("/music"), Path::new("/music")));
+    }
+
+    #[test]
+    fn test_path_limit_includes_root() {
+        let mut planner = Planner::parse("%title%", "/music").unwrap();
+        let mut mode = PathMode::linux();
+        mode.set_path_limit(Some(12));
+        planner.set_path_mode(mode);
+        let files = vec![(PathBuf::from("/in/1.mp3"), track("abcdef"))];
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(plan.moves[0].to, PathBuf::from("/music/a.mp3"));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let files = vec![
+            (PathBuf::from("/in/1.mp3"), track("a")),
+            (PathBuf::from("/in/2.mp3"), track("A")),
+            (PathBuf::from("/in/3.mp3"), track("a")),
+        ];
+        let mut planner = Planner::parse("%title%", "/out").unwrap();
+        planner.set_case_insensitive(false);
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(
+            plan.conflicts,
+            vec![Conflict::Duplicate(PathBuf::from("/out/a.mp3"), vec![0, 2])]
+        );
+        planner.set_case_insensitive(true);
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(
+            plan.conflicts,
+            vec![Conflict::Duplicate(
+                PathBuf::from("/out/a.mp3"),
+                vec![0, 1, 2]
+            )]
+        );
+        assert!(plan
+            .report()
+            .ends_with("conflict: 3 files would be named /out/a.mp3\n"));
+    }
+
+    #[test]
+    fn test_execute_and_undo() {
+        let dir = temp_dir("execute");
+        fs::write(dir.join("1.mp3"), "one").unwrap();
+        fs::write(dir.join("2.mp3"), "two").unwrap();
+        /* the two files swap names */
+        let files = vec![
+            (dir.join("1.mp3"), track("2")),
+            (dir.join("2.mp3"), track("1")),
+        ];
+        let planner = Planner::parse("%title%", &dir).unwrap();
+        let plan = planner.plan(&files).unwrap();
+        assert!(plan.is_ok(), "{}", plan.report());
+        let journal = dir.join("journal");
+        plan.execute(&journal).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("1.mp3")).unwrap(), "two");
+        assert_eq!(fs::read_to_string(dir.join("2.mp3")).unwrap(), "one");
+
+        undo(&journal).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("1.mp3")).unwrap(), "one");
+        assert_eq!(fs::read_to_string(dir.join("2.mp3")).unwrap(), "two");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_exists() {
+        let dir = temp_dir("exists");
+        fs::write(dir.join("1.mp3"), "").unwrap();
+        fs::write(dir.join("A.mp3"), "").unwrap();
+        let files = vec![(dir.join("1.mp3"), track("a"))];
+        let mut planner = Planner::parse("%title%", &dir).unwrap();
+        planner.set_case_insensitive(true);
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(plan.conflicts, vec![Conflict::Exists(dir.join("a.mp3"), 0)]);
+        assert_eq!(
+            plan.execute(dir.join("journal")).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(dir.join("1.mp3").exists());
+        assert!(!dir.join("journal").exists());
+
+        planner.set_case_insensitive(false);
+        assert!(planner.plan(&files).unwrap().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = temp_dir("rollback");
+        fs::write(dir.join("1.mp3"), "").unwrap();
+        let moves = vec![
+            (dir.join("1.mp3"), dir.join("a.mp3")),
+            (dir.join("missing.mp3"), dir.join("b.mp3")),
+        ];
+        assert!(apply(&moves).is_err());
+        assert!(dir.join("1.mp3").exists());
+        assert!(!dir.join("a.mp3").exists());
+
+        let plan = Plan {
+            moves: moves
+                .into_iter()
+                .enumerate()
+                .map(|(index, (from, to))| Move { index, from, to })
+                .collect(),
+            conflicts: vec![],
+        };
+        assert!(plan.execute(dir.join("journal")).is_err());
+        assert!(dir.join("1.mp3").exists());
+        assert!(!dir.join("journal").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_escape() {
+        let path = PathBuf::from("a\\b\tc\nd");
+        assert_eq!(escape(&path).unwrap(), "a\\\\b\\tc\\nd");
+        assert_eq!(unescape(&escape(&path).unwrap()), path);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::program::Program;
+use crate::track::Track;
+use crate::types::Error;
+
+use std::cmp::Ordering;
+
+/// The order of a [`SortKey`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+/// A program whose output tracks are sorted by, in either direction
+#[derive(Clone, Debug)]
+pub struct SortKey {
+    program: Program,
+    direction: Direction,
+}
+
+impl SortKey {
+    /// Constructs a new `SortKey` from an already parsed `program`
+    pub fn new(program: Program, direction: Direction) -> Self {
+        SortKey { program, direction }
+    }
+
+    /// Parses `instr` into a new `SortKey`
+    ///
+    /// # Examples
+    /// ```
+    /// # use titleformat_rs::sort::{Direction, SortKey};
+    /// let key = SortKey::parse("%album% - %tracknumber%", Direction::Ascending).unwrap();
+    /// ```
+    pub fn parse(instr: &str, direction: Direction) -> Result<Self, Error> {
+        let mut program = Program::new();
+        program.parse(instr)?;
+        Ok(SortKey::new(program, direction))
+    }
+}
+
+/* the next run of either digits or non-digits */
+fn chunk(s: &str) -> (&str, &str) {
+    let digits = s.starts_with(|c: char| c.is_ascii_digit());
+    let end = s
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/* compares two runs of digits by their value without overflowing */
+fn cmp_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Compares `a` and `b` the way a person would: ignoring case and with runs
+/// of digits compared by their numeric value, so "Track 9" comes before
+/// "track 10".
+///
+/// # Examples
+/// ```
+/// # use titleformat_rs::sort::natural_cmp;
+/// # use std::cmp::Ordering;
+/// assert_eq!(natural_cmp("Track 9", "track 10"), Ordering::Less);
+/// assert_eq!(natural_cmp("ABC", "abc"), Ordering::Equal);
+/// ```
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => {}
+        }
+        let (ca, rest_a) = chunk(a);
+        let (cb, rest_b) = chunk(b);
+        let both_digits = ca.starts_with(|c: char| c.is_ascii_digit())
+            && cb.starts_with(|c: char| c.is_ascii_digit());
+        let ord = if both_digits {
+            cmp_numbers(ca, cb)
+        } else {
+            ca.chars()
+                .flat_map(char::to_lowercase)
+                .cmp(cb.chars().flat_map(char::to_lowercase))
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+        a = rest_a;
+        b = rest_b;
+    }
+}
+
+/// Returns the indices of `tracks` in the order given by `keys`.  Each key
+/// is evaluated once per track, later keys only break ties of the earlier
+/// ones and tracks that compare equal keep their original order.
+///
+/// # Examples
+/// ```
+/// # use titleformat_rs::sort::{sorted_indices, Direction, SortKey};
+/// # use std::collections::HashMap;
+/// let tracks: Vec<HashMap<String, Vec<String>>> = ["10", "9", "1"]
+///     .iter()
+///     .map(|n| HashMap::from([("tracknumber".to_string(), vec![n.to_string()])]))
+///     .collect();
+/// let keys = [SortKey::parse("%tracknumber%", Direction::Ascending).unwrap()];
+/// assert_eq!(sorted_indices(&tracks, &keys).unwrap(), vec![2, 1, 0]);
+/// ```
+pub fn sorted_indices<T: Track>(tracks: &[T], keys: &[SortKey]) -> Result<Vec<usize>, Error> {
+    let mut indices: Vec<usize> = (0..tracks.len()).collect();
+    sort_indices(tracks, &mut indices, keys)?;
+    Ok(indices)
+}
+
+/* sorts some of the indices of tracks, e.g. those matching a query */
+pub(crate) fn sort_indices<T: Track>(
+    tracks: &[T],
+    indices: &mut Vec<usize>,
+    keys: &[SortKey],
+) -> Result<(), Error> {
+    let values = indices
+        .iter()
+        .map(|&i| {
+            keys.iter()
+                .map(|key| Ok(key.program.evaluate(&tracks[i])?.val))
+                .collect::<Result<Vec<String>, Error>>()
+        })
+        .collect::<Result<Vec<Vec<String>>, Error>>()?;
+
+    let mut order: Vec<usize> = (0..indices.len()).collect();
+    /* sort_by is stable */
+    order.sort_by(|&a, &b| {
+        keys.iter()
+            .zip(values[a].iter().zip(values[b].iter()))
+            .map(|(key, (va, vb))| match key.direction {
+                Direction::Ascending => natural_cmp(va, vb),
+                Direction::Descending => natural_cmp(vb, va),
+            })
+            .find(|ord| *ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+    *indices = order.into_iter().map(|i| indices[i]).collect();
+    Ok(())
+}
+
+/// Sorts `tracks` in place by `keys`, see [`sorted_indices`].  On error the
+/// tracks are left untouched.
+///
+/// # Examples
+/// ```
+/// # use titleformat_rs::sort::{sort, Direction, SortKey};
+/// # use std::collections::HashMap;
+/// let mut tracks: Vec<HashMap<String, Vec<String>>> = [("B", "2"), ("a", "2"), ("A", "1")]
+///     .iter()
+///     .map(|(album, disc)| {
+///         HashMap::from([
+///             ("album".to_string(), vec![album.to_string()]),
+///             ("discnumber".to_string(), vec![disc.to_string()]),
+///         ])
+///     })
+///     .collect();
+/// let keys = [
+///     SortKey::parse("%album%", Direction::Ascending).unwrap(),
+///     SortKey::parse("%discnumber%", Direction::Descending).unwrap(),
+/// ];
+/// sort(&mut tracks, &keys).unwrap();
+/// let albums: Vec<&str> = tracks.iter().map(|t| t["album"][0].as_str()).collect();
+/// assert_eq!(albums, vec!["a", "A", "B"]);
+/// ```
+pub fn sort<T: Track>(tracks: &mut [T], keys: &[SortKey]) -> Result<(), Error> {
+    let mut order = sorted_indices(tracks, keys)?;
+    /* apply the permutation following its cycles: position i takes the
+     * track at order[i] */
+    for i in 0..order.len() {
+        let mut current = i;
+        while order[current] != i {
+            let next = order[current];
+            tracks.swap(current, next);
+            order[current] = current;
+            current = next;
+        }
+        order[current] = current;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn tracks(values: &[&str]) -> Vec<HashMap<String, Vec<String>>> {
+        values
+            .iter()
+            .map(|v| HashMap::from([(String::from("k"), vec![String::from(*v)])]))
+            .collect()
+    }
+
+    fn values(tracks: &[HashMap<String, Vec<String>>]) -> Vec<&str> {
+        tracks.iter().map(|t| t["k"][0].as_str()).collect()
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("a2", "a10"), Ordering::Less);
+        assert_eq!(natural_cmp("a02", "a2"), Ordering::Equal);
+        assert_eq!(natural_cmp("b", "A"), Ordering::Greater);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+        assert_eq!(natural_cmp("1a", "a"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+        assert_eq!(natural_cmp("Éa", "éb"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut t = tracks(&["10", "b", "9", "B", "a", "1"]);
+        let keys = [SortKey::parse("%k%", Direction::Ascending).unwrap()];
+        sort(&mut t, &keys).unwrap();
+        assert_eq!(values(&t), vec!["1", "9", "10", "a", "b", "B"]);
+
+        let keys = [SortKey::parse("%k%", Direction::Descending).unwrap()];
+        sort(&mut t, &keys).unwrap();
+        assert_eq!(values(&t), vec!["b", "B", "a", "10", "9", "1"]);
+    }
+
+    #[test]
+    fn test_sort_permutation() {
+        let v = ["e", "c", "a", "f", "d", "b", "g"];
+        let mut t = tracks(&v);
+        let keys = [SortKey::parse("%k%", Direction::Ascending).unwrap()];
+        sort(&mut t, &keys).unwrap();
+        assert_eq!(values(&t), vec!["a", "b", "c", "d", "e", "f", "g"]);
+    }
+
+    #[test]
+    fn test_sort_error() {
+        let mut t = tracks(&["b", "a"]);
+        let keys = [SortKey::parse("$add()", Direction::Ascending).unwrap()];
+        assert!(sort(&mut t, &keys).is_err());
+        assert_eq!(values(&t), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_no_keys() {
+        let t = tracks(&["b", "a", "c"]);
+        assert_eq!(sorted_indices(&t, &[]).unwrap(), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+/* Everything a program can know about a single track: the tags (metadata)
+ * and, separately, the technical information about the stream itself
+ * (sample rate, bitrate, codec, ...) so that a tag can never shadow a stream
+ * property. */
+pub trait Track {
+    /// The tags of the track
+    fn metadata(&self) -> &HashMap<String, Vec<String>>;
+
+    /// The technical information of the track, if any is known
+    fn info(&self) -> Option<&HashMap<String, String>> {
+        None
+    }
+}
+
+/* plain metadata is a track without any technical information */
+impl Track for HashMap<String, Vec<String>> {
+    fn metadata(&self) -> &HashMap<String, Vec<String>> {
+        self
+    }
+}
+
+/// A track with both metadata and technical information
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::track::TrackData;
+/// # use titleformat_rs::program::Program;
+/// let mut track = TrackData::default();
+/// track.metadata.insert("codec".into(), vec!["not the codec".into()]);
+/// track.info.insert("codec".into(), "FLAC".into());
+/// let mut program = Program::new();
+/// program.parse("%codec%").unwrap();
+/// assert_eq!(program.evaluate(&track).unwrap().val, "FLAC");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackData {
+    pub metadata: HashMap<String, Vec<String>>,
+    pub info: HashMap<String, String>,
+}
+
+impl TrackData {
+    /// Constructs a new `TrackData` from metadata and technical information
+    pub fn new(metadata: HashMap<String, Vec<String>>, info: HashMap<String, String>) -> Self {
+        TrackData { metadata, info }
+    }
+}
+
+impl Track for TrackData {
+    fn metadata(&self) -> &HashMap<String, Vec<String>> {
+        &self.metadata
+    }
+
+    fn info(&self) -> Option<&HashMap<String, String>> {
+        Some(&self.info)
+    }
+}
@@ -0,0 +1,174 @@
+use crate::program::Program;
+use crate::track::Track;
+use crate::types::Error;
+
+use std::collections::HashMap;
+
+/// A node of a tree built by [`build`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Node {
+    /// The text of this level, empty for the root
+    pub label: String,
+    /// The nodes of the next level, in the order they were first seen
+    pub children: Vec<Node>,
+    /// The indices of all the tracks under this node, in order and once each
+    pub tracks: Vec<usize>,
+}
+
+impl Node {
+    /// Returns the descendant found by following the labels of `path`
+    pub fn find(&self, path: &[&str]) -> Option<&Node> {
+        path.iter().try_fold(self, |node, label| {
+            node.children.iter().find(|child| child.label == *label)
+        })
+    }
+}
+
+/* a node while the tree is being built, with an index of its children */
+#[derive(Default)]
+struct Builder {
+    label: String,
+    children: Vec<Builder>,
+    index: HashMap<String, usize>,
+    tracks: Vec<usize>,
+}
+
+impl Builder {
+    fn insert<'s, I: Iterator<Item = &'s str>>(&mut self, mut path: I, track: usize) {
+        /* a track fanned out to several branches is only listed once */
+        if self.tracks.last() != Some(&track) {
+            self.tracks.push(track);
+        }
+        let Some(label) = path.next() else {
+            return;
+        };
+        let i = match self.index.get(label) {
+            Some(i) => *i,
+            None => {
+                self.children.push(Builder {
+                    label: String::from(label),
+                    ..Builder::default()
+                });
+                self.index
+                    .insert(String::from(label), self.children.len() - 1);
+                self.children.len() - 1
+            }
+        };
+        self.children[i].insert(path, track);
+    }
+
+    fn build(self) -> Node {
+        Node {
+            label: self.label,
+            children: self.children.into_iter().map(Builder::build).collect(),
+            tracks: self.tracks,
+        }
+    }
+}
+
+/// Builds a tree like foobar2000's Album List from the output of `program`
+/// for each of `tracks`: `|` separates the levels of the tree and a
+/// multi-value field (`%<genre>%`) puts the track under a branch for each of
+/// its values.  A `|` in a tag value also starts a new level.
+///
+/// # Examples
+///
+/// ```
+/// # use titleformat_rs::program::Program;
+/// # use titleformat_rs::tree;
+/// # use std::collections::HashMap;
+/// let tracks: Vec<HashMap<String, Vec<String>>> = [("A", "X"), ("A", "Y"), ("B", "Z")]
+///     .iter()
+///     .map(|(album, title)| {
+///         HashMap::from([
+///             ("album".to_string(), vec![album.to_string()]),
+///             ("title".to_string(), vec![title.to_string()]),
+///         ])
+///     })
+///     .collect();
+/// let mut program = Program::new();
+/// program.parse("%album%|%title%").unwrap();
+/// let root = tree::build(&program, &tracks).unwrap();
+/// assert_eq!(root.children.len(), 2);
+/// assert_eq!(root.children[0].label, "A");
+/// assert_eq!(root.children[0].tracks, vec![0, 1]);
+/// assert_eq!(root.find(&["B", "Z"]).unwrap().tracks, vec![2]);
+/// ```
+pub fn build<T: Track>(program: &Program, tracks: &[T]) -> Result<Node, Error> {
+    let mut root = Builder::default();
+    for (i, track) in tracks.iter().enumerate() {
+        for output in program.run_multi(track)? {
+            root.insert(output.split('|'), i);
+        }
+    }
+    Ok(root.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(tags: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        tags.iter()
+            .map(|(k, v)| {
+                (
+                    String::from(*k),
+                    v.iter().map(|s| String::from(*s)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn labels(node: &Node) -> Vec<&str> {
+        node.children.iter().map(|c| c.label.as_str()).collect()
+    }
+
+    #[test]
+    fn test_multi_value_fan_out() {
+        let tracks = vec![
+            track(&[("genre", &["Rock", "Pop"]), ("title", &["a"])]),
+            track(&[("genre", &["Pop"]), ("title", &["b"])]),
+        ];
+        let mut program = Program::new();
+        program.parse("%<genre>%|%title%").unwrap();
+        let root = build(&program, &tracks).unwrap();
+        assert_eq!(root.label, "");
+        assert_eq!(root.tracks, vec![0, 1]);
+        assert_eq!(labels(&root), vec!["Rock", "Pop"]);
+        assert_eq!(root.find(&["Rock"]).unwrap().tracks, vec![0]);
+        assert_eq!(root.find(&["Pop"]).unwrap().tracks, vec![0, 1]);
+        assert_eq!(labels(root.find(&["Pop"]).unwrap()), vec!["a", "b"]);
+        assert_eq!(root.find(&["Pop", "c"]), None);
+    }
+
+    #[test]
+    fn test_optional_levels() {
+        let tracks = vec![
+            track(&[("discnumber", &["1"]), ("title", &["a"])]),
+            track(&[("title", &["b"])]),
+        ];
+        let mut program = Program::new();
+        program
+            .parse("$if(%discnumber%,%discnumber%|)%title%")
+            .unwrap();
+        let root = build(&program, &tracks).unwrap();
+        assert_eq!(labels(&root), vec!["1", "b"]);
+        assert_eq!(labels(&root.children[0]), vec!["a"]);
+        assert!(root.children[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_error() {
+        let mut program = Program::new();
+        program.parse("$add()").unwrap();
+        assert!(build(&program, &[track(&[])]).is_err());
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut program = Program::new();
+        program.parse("%album%").unwrap();
+        let tracks: Vec<HashMap<String, Vec<String>>> = vec![];
+        assert_eq!(build(&program, &tracks).unwrap(), Node::default());
+    }
+}
@@ -11,6 +11,9 @@ pub enum Error {
     UndefinedFunction(String),
     OutOfRange,
     ParseError,
+    WriteError(String),
+    QueryParseError(usize, String),
+    PatternError(String),
 }
 
 use crate::types::Error::*;
@@ -29,6 +32,11 @@ impl fmt::Display for Error {
             UndefinedFunction(ref varname) => write!(f, "Undefined Function: {}", varname),
             ParseError => write!(f, "Unable the parse the input. Please recheck."),
             OutOfRange => write!(f, "Computed value out of range"),
+            WriteError(ref reason) => write!(f, "Failed to write the output: {}", reason),
+            QueryParseError(pos, ref reason) => {
+                write!(f, "Unable to parse the query at {}: {}", pos, reason)
+            }
+            PatternError(ref reason) => write!(f, "Unable to match with the pattern: {}", reason),
         }
     }
 }
@@ -37,6 +45,7 @@ impl fmt::Display for Error {
 pub enum Expr {
     Literal(String),
     Variable(String),                 /* %variable% */
+    MultiVariable(String),            /* %<variable>% */
     Conditional(Vec<Expr>),           /* [expression] */
     FuncCall(String, Vec<Vec<Expr>>), /* $func(args) */
     ExprValue(Value),                 /* program internal value for resolved expressions */